@@ -0,0 +1,67 @@
+use super::*;
+use crate::convergence::Convergence;
+
+/// Upper bound on the extra samples a pixel can get per iteration.
+pub const MAX_EXTRA_SAMPLES: u32 = 7;
+/// Set in the x coordinate hashed for the rounding of allocations, which is never set for a pixel,
+/// so the rounding is independent of the samples traced.
+const ALLOCATION_DOMAIN: u32 = 1 << 31;
+
+/// Spends extra samples per iteration on pixels whose relative error is above average.
+///
+/// How many samples a pixel gets depends on those it already has, but each iteration adds the mean
+/// of its samples and [`Convergence`] weighs every iteration equally, so the display stays
+/// unbiased.
+pub struct AdaptiveSampler {
+    extra: Tex2d<u32>,
+    /// Sum of `extra` over all pixels, accumulated by [`Self::allocate`].
+    total: Buffer<u32>,
+    /// Extra samples traced per iteration, as last allocated.
+    pub extra_samples: u64,
+    pub enabled: bool,
+    /// Mean number of extra samples per pixel and iteration.
+    pub budget: f32,
+}
+impl AdaptiveSampler {
+    pub fn new(size: Vec2<u32>) -> Self {
+        Self {
+            extra: DEVICE.create_tex2d(PixelStorage::Int1, size.x, size.y, 1),
+            total: DEVICE.create_buffer_from_fn(1, |_| 0),
+            extra_samples: 0,
+            enabled: false,
+            budget: 1.0,
+        }
+    }
+    pub fn extra(&self, pixel: Expr<Vec2<u32>>) -> Expr<u32> {
+        self.extra.read(pixel)
+    }
+    #[tracked]
+    pub fn clear(&self, pixel: Expr<Vec2<u32>>) {
+        self.extra.write(pixel, 0);
+    }
+    /// Distributes `budget` extra samples proportionally to the relative error at `pixel`,
+    /// with stochastic rounding that differs every `iteration`.
+    #[tracked]
+    pub fn allocate(
+        &self,
+        convergence: &Convergence,
+        pixel: Expr<Vec2<u32>>,
+        sum: Expr<Radiance>,
+        mean_error: Expr<f32>,
+        budget: Expr<f32>,
+        iteration: Expr<u32>,
+    ) {
+        let rand = pcg3df(Vec3::expr(pixel.x | ALLOCATION_DOMAIN, pixel.y, iteration)).x;
+        let error = convergence.relative_error(pixel, sum);
+        let extra = (error / keter::max(mean_error, 1e-6) * budget + rand).floor();
+        let extra = extra.clamp(0.0, MAX_EXTRA_SAMPLES as f32).cast_u32();
+        self.extra.write(pixel, extra);
+        self.total.atomic_fetch_add(0, extra);
+    }
+    /// Sum of the extra samples allocated since the last call. Resets the accumulator.
+    pub fn take_total(&self) -> u64 {
+        let total = self.total.copy_to_vec()[0];
+        self.total.copy_from(&[0]);
+        total as u64
+    }
+}
@@ -1,6 +1,11 @@
 use core::f32;
 
 use super::*;
+use crate::utils::{diffuse_direction, pcg, pcgf};
+use crate::voxel::TRANSMITTANCE_CUTOFF;
+
+/// Diffuse bounces per traced ray, after which the path is terminated.
+pub(crate) const MAX_BOUNCES: u32 = 16;
 
 #[derive(Debug, Clone, Copy, PartialEq, Value)]
 #[repr(C)]
@@ -8,12 +13,38 @@ pub struct Object {
     pub center: Vec2<f32>,
     // Assume circular; change sometime?
     pub radius: f32,
-    pub refraction_index: f32,
-    pub color: Color,
+    pub material: Material,
+}
+
+/// The default scene: a small light to the right of two glass lenses, in a world of width `size`.
+pub fn lenses(size: f32) -> Vec<Object> {
+    let scale = size / 1024.0;
+    vec![
+        Object {
+            center: Vec2::new(3.0 * size / 4.0, size / 2.0),
+            radius: 5.0 * scale,
+            material: Material::new(Vec3::splat(20.0), Vec3::splat(2.0 / scale)),
+        },
+        Object {
+            center: Vec2::new(size / 2.0, size / 2.0),
+            radius: 100.0 * scale,
+            material: Material::glass(1.5),
+        },
+        Object {
+            center: Vec2::new(size / 4.0, size / 2.0),
+            radius: 50.0 * scale,
+            material: Material::glass(1.5),
+        },
+    ]
 }
 
+/// Number of objects an [`AnalyticTracer`] has room for by default.
+pub const MAX_OBJECTS: usize = 256;
+
 pub struct AnalyticTracer {
+    // Only the first `len` objects are live; the rest is capacity for editing.
     pub objects: Buffer<Object>,
+    len: Buffer<u32>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Value)]
@@ -31,6 +62,12 @@ pub struct TracedRay {
     pub fluence: Fluence,
     pub final_pos: Vec2<f32>,
     pub final_dir: Vec2<f32>,
+    /// Distance travelled, less than the requested length if the path was terminated.
+    pub length: f32,
+    /// Traversal steps taken by the tracer, a measure of its cost.
+    pub steps: u32,
+    /// Interfaces refracted through.
+    pub refractions: u32,
 }
 
 #[tracked]
@@ -53,11 +90,62 @@ fn intersect_circle(
     }
 }
 
+/// Samples the slope of a microfacet normal from the 2D analogue of the GGX distribution,
+/// `D(s) = (1 + s^2 / alpha^2)^(-3/2) / (2 alpha)`.
+#[tracked]
+fn sample_slope(alpha: Expr<f32>, rand: Expr<f32>) -> Expr<f32> {
+    let x = rand * 2.0 - 1.0;
+    alpha * x / keter::max(1.0 - x.sqr(), 1e-6).sqrt()
+}
+
+/// Unpolarized Fresnel reflectance for light arriving at `cos_i` to the normal, with `eta` the
+/// ratio of the incident to the transmitted index of refraction; 1 for total internal reflection.
+#[tracked]
+fn fresnel(cos_i: Expr<f32>, eta: Expr<f32>) -> Expr<f32> {
+    let sin_t2 = eta.sqr() * (1.0 - cos_i.sqr());
+    if sin_t2 >= 1.0 {
+        1.0_f32.expr()
+    } else {
+        let cos_t = (1.0 - sin_t2).sqrt();
+        let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        (rs.sqr() + rp.sqr()) * 0.5
+    }
+}
+
 impl AnalyticTracer {
     pub fn new(objects: &[Object]) -> Self {
-        Self {
-            objects: DEVICE.create_buffer_from_slice(objects),
+        Self::with_capacity(objects, MAX_OBJECTS.max(objects.len()))
+    }
+    pub fn with_capacity(objects: &[Object], capacity: usize) -> Self {
+        let this = Self {
+            objects: DEVICE.create_buffer(capacity),
+            len: DEVICE.create_buffer(1),
+        };
+        this.upload(objects);
+        this
+    }
+    pub fn capacity(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn memory_bytes(&self) -> usize {
+        self.capacity() * std::mem::size_of::<Object>() + std::mem::size_of::<u32>()
+    }
+    /// Replaces the objects on the device.
+    pub fn upload(&self, objects: &[Object]) {
+        assert!(
+            objects.len() <= self.capacity(),
+            "AnalyticTracer has room for {} objects, got {}",
+            self.capacity(),
+            objects.len()
+        );
+        if !objects.is_empty() {
+            self.objects.view(..objects.len()).copy_from(objects);
         }
+        self.len.copy_from(&[objects.len() as u32]);
+    }
+    fn len_expr(&self) -> Expr<u32> {
+        self.len.read(0_u32.expr())
     }
     #[tracked]
     fn trace_once(
@@ -73,7 +161,7 @@ impl AnalyticTracer {
             leaving: false,
         }
         .var();
-        for i in 0_u32.expr()..self.objects.len_expr_u32() {
+        for i in 0_u32.expr()..self.len_expr() {
             let object = self.objects.read(i);
             let (min_t, max_t, _penetration, hit) =
                 intersect_circle(pos - object.center, dir, object.radius);
@@ -94,62 +182,124 @@ impl AnalyticTracer {
         }
         **closest_hit
     }
-    // Needs RNG; otherwise refraction only.
+    /// Traces `len` along the ray, reflecting off and refracting through objects and bouncing off
+    /// diffuse ones, with `seed` choosing between them and perturbing rough interfaces.
     #[tracked]
     pub fn trace(
         &self,
         pos: Expr<Vec2<f32>>,
         dir: Expr<Vec2<f32>>,
         len: Expr<f32>,
+        seed: Expr<u32>,
     ) -> Expr<TracedRay> {
         let pos = pos.var();
         let dir = dir.var();
         let len = len.var();
-        let color = Color::empty().var();
-        let refr_index = 1.0_f32.var();
+        let material = self.material_at(**pos).var();
         let fluence = Fluence::empty().var();
-        for i in 0_u32.expr()..self.objects.len_expr_u32() {
-            let object = self.objects.read(i);
-            if (pos - object.center).length() < object.radius {
-                *color = object.color;
-                *refr_index = object.refraction_index;
-            }
-        }
+        let bounces = 0_u32.var();
+        // Interactions so far, each drawing two random numbers.
+        let events = 0_u32.var();
+        let length = 0.0_f32.var();
+        let steps = 0_u32.var();
+        let refractions = 0_u32.var();
         loop {
             let hit = self.trace_once(**pos, 0.001_f32.expr(), **dir);
+            *steps += 1;
             if hit.distance > len {
                 *pos += len * dir;
-                *fluence = fluence.over(color.to_fluence(**len));
+                *length += len;
+                *fluence = fluence.over(material.color().to_fluence(**len));
                 break;
             }
             *pos += hit.distance * dir;
             *len -= hit.distance;
-            *fluence = fluence.over(color.to_fluence(hit.distance));
+            *length += hit.distance;
+            *fluence = fluence.over(material.color().to_fluence(hit.distance));
             let normal = if hit.leaving { hit.normal } else { -hit.normal };
             // let a = normal.dot(dir) >= 0.0;
             // lc_assert!(a);
             let obj = self.objects.read(hit.object);
-            let next_refr_index = if hit.leaving {
-                1.0_f32.expr()
+            let rand = Vec2::expr(
+                pcgf(seed ^ pcg(events * 2)),
+                pcgf(seed ^ pcg(events * 2 + 1)),
+            );
+            *events += 1;
+            if !hit.leaving && obj.material.is_diffuse() {
+                *fluence = fluence.over(Fluence::expr(Vec3::splat_expr(0.0), obj.material.albedo));
+                *bounces += 1;
+                if bounces > MAX_BOUNCES || (fluence.transmittance < TRANSMITTANCE_CUTOFF).all() {
+                    *fluence.transmittance = Vec3::splat(0.0);
+                    break;
+                }
+                *dir = diffuse_direction(hit.normal, rand.x);
+                continue;
+            }
+            let next_material = if hit.leaving {
+                Material::empty().expr()
             } else {
-                obj.refraction_index
+                obj.material
             };
-            let angle = (1.0 - normal.dot(dir).sqr()).sqrt() * refr_index / next_refr_index; // sin theta
-            if angle.abs() >= 1.0 {
-                *fluence =
-                    fluence.over(Fluence::expr(Vec3::splat_expr(0.0), Vec3::splat_expr(0.0)));
-                break;
-            }
+            let eta = material.ior / next_material.ior;
             let tangent = Vec2::expr(normal.y, -normal.x);
-            let sign = dir.dot(tangent).signum();
-            *dir = angle * sign * tangent + (1.0 - angle.sqr()).sqrt() * normal;
-            *refr_index = next_refr_index;
-            *color = obj.color;
+            let slope = sample_slope(obj.material.roughness.sqr(), rand.x);
+            let micro = (normal + slope * tangent).normalize();
+            // Microfacets facing away from the ray can't be hit; fall back to the smooth normal.
+            let micro = if micro.dot(dir) > 0.0 { micro } else { normal };
+            let cos_i = micro.dot(dir);
+            let reflected = dir - 2.0 * cos_i * micro;
+            let smooth_reflected = dir - 2.0 * normal.dot(dir) * normal;
+            if rand.y < fresnel(cos_i, eta) {
+                *dir = if reflected.dot(normal) < 0.0 {
+                    reflected
+                } else {
+                    smooth_reflected
+                };
+            } else {
+                let angle = (1.0 - cos_i.sqr()).sqrt() * eta; // sin theta
+                let micro_tangent = Vec2::expr(micro.y, -micro.x);
+                let sign = dir.dot(micro_tangent).signum();
+                let refracted = angle * sign * micro_tangent + (1.0 - angle.sqr()).sqrt() * micro;
+                if refracted.dot(normal) > 0.0 {
+                    *dir = refracted;
+                    *material = next_material;
+                    *refractions += 1;
+                } else {
+                    *dir = smooth_reflected;
+                }
+            }
         }
         TracedRay::from_comps_expr(TracedRayComps {
             fluence: **fluence,
             final_pos: **pos,
             final_dir: **dir,
+            length: **length,
+            steps: **steps,
+            refractions: **refractions,
         })
     }
 }
+
+impl PathTracer for AnalyticTracer {
+    fn trace_path(
+        &self,
+        pos: Expr<Vec2<f32>>,
+        dir: Expr<Vec2<f32>>,
+        len: Expr<f32>,
+        seed: Expr<u32>,
+    ) -> Expr<TracedRay> {
+        self.trace(pos, dir, len, seed)
+    }
+    /// The material of the last object containing `pos`, or empty space.
+    #[tracked]
+    fn material_at(&self, pos: Expr<Vec2<f32>>) -> Expr<Material> {
+        let material = Material::empty().var();
+        for i in 0_u32.expr()..self.len_expr() {
+            let object = self.objects.read(i);
+            if (pos - object.center).length() < object.radius {
+                *material = object.material;
+            }
+        }
+        **material
+    }
+}
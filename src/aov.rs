@@ -0,0 +1,116 @@
+//! Diagnostic per-pixel outputs (arbitrary output variables) of the guided paths, for debugging
+//! the renderer's correctness and cost.
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Aov {
+    /// The rendered radiance, kept in the display rather than in [`Aovs`].
+    Radiance,
+    /// Transmittance of the whole path.
+    Transmittance,
+    /// Distance travelled over all segments of the path.
+    PathLength,
+    /// Traversal steps of the tracer over all segments, a heatmap of its cost.
+    Steps,
+    /// Interfaces refracted through over all segments.
+    Refractions,
+    /// Index of the direction chosen in the last cascade of the guide, by the latest sample since
+    /// indices can't be averaged.
+    GuideLeaf,
+    /// Ratio of the mixture pdf of the chosen direction to the uniform pdf.
+    Bias,
+}
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Radiance,
+        Aov::Transmittance,
+        Aov::PathLength,
+        Aov::Steps,
+        Aov::Refractions,
+        Aov::GuideLeaf,
+        Aov::Bias,
+    ];
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Radiance => "radiance",
+            Aov::Transmittance => "transmittance",
+            Aov::PathLength => "length",
+            Aov::Steps => "steps",
+            Aov::Refractions => "refractions",
+            Aov::GuideLeaf => "leaf",
+            Aov::Bias => "bias",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+    /// Whether samples of the AOV are averaged, rather than the latest one kept.
+    pub fn is_averaged(self) -> bool {
+        self != Aov::GuideLeaf
+    }
+}
+
+/// Per-pixel sums of every [`Aov`] but [`Aov::Radiance`] over the samples added to the display
+/// while they were recorded, which is only while they are looked at since it slows tracing down;
+/// or the latest sample, for those that aren't [averaged](Aov::is_averaged).
+pub struct Aovs {
+    sums: Buffer<Vec3<f32>>,
+    samples: Tex2d<u32>,
+    size: Vec2<u32>,
+    /// Whether [`Self::record`] is called for new samples.
+    pub enabled: bool,
+}
+impl Aovs {
+    pub fn new(size: Vec2<u32>) -> Self {
+        Self {
+            sums: DEVICE.create_buffer(((Aov::ALL.len() - 1) as u32 * size.x * size.y) as usize),
+            samples: DEVICE.create_tex2d(PixelStorage::Int1, size.x, size.y, 1),
+            size,
+            enabled: false,
+        }
+    }
+    pub fn memory_bytes(&self) -> usize {
+        self.sums.len() * std::mem::size_of::<Vec3<f32>>()
+    }
+    #[tracked]
+    fn index(&self, pixel: Expr<Vec2<u32>>, aov: Expr<u32>) -> Expr<u32> {
+        (aov - 1) * self.size.x * self.size.y + pixel.x + pixel.y * self.size.x
+    }
+    #[tracked]
+    pub fn clear(&self, pixel: Expr<Vec2<u32>>) {
+        self.samples.write(pixel, 0);
+        for aov in (1..Aov::ALL.len() as u32) {
+            self.sums
+                .write(self.index(pixel, aov.expr()), Vec3::splat_expr(0.0_f32));
+        }
+    }
+    /// Adds a sample of every AOV after [`Aov::Radiance`], in order.
+    #[tracked]
+    pub fn record(&self, pixel: Expr<Vec2<u32>>, values: [Expr<Vec3<f32>>; 6]) {
+        self.samples.write(pixel, self.samples.read(pixel) + 1);
+        for (aov, value) in Aov::ALL[1..].iter().zip(values) {
+            let index = self.index(pixel, (*aov as u32).expr());
+            if aov.is_averaged() {
+                self.sums.write(index, self.sums.read(index) + value);
+            } else {
+                self.sums.write(index, value);
+            }
+        }
+    }
+    /// Mean of `aov` at `pixel`, or its latest sample if it isn't averaged; `aov` mustn't be
+    /// [`Aov::Radiance`].
+    #[tracked]
+    pub fn mean(&self, pixel: Expr<Vec2<u32>>, aov: Expr<u32>) -> Expr<Vec3<f32>> {
+        let sum = self.sums.read(self.index(pixel, aov));
+        if aov == Aov::GuideLeaf as u32 {
+            sum
+        } else {
+            sum / keter::max(self.samples.read(pixel), 1).cast_f32()
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use super::*;
+use crate::utils::{pcg3d, pcgf_host};
+use crate::voxel::{Block, BlockSize, with_block};
+
+/// Compares traversal step counts of the voxel tracer for each block size and
+/// different numbers of occupancy levels; a single level is the traversal from before the
+/// hierarchy, cell by cell through nonempty blocks and block by block through the rest.
+pub fn occupancy() {
+    println!("block size, levels, mean steps, max steps, time (ms)");
+    for block_size in BlockSize::ALL {
+        with_block!(block_size, B => occupancy_with::<B>());
+    }
+}
+
+fn occupancy_with<B: Block>() {
+    let size = Vec2::splat(DISPLAY_SIZE);
+    let scene = Scene::sunflower4();
+
+    for levels in [1, 2, 3, 4, 6] {
+        let world = VoxelTracer::<B>::with_levels(size, levels);
+        scene.draw(&world);
+
+        let steps = DEVICE.create_buffer::<u32>((size.x * size.y) as usize);
+        let trace_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            let pos = pixel.cast_f32() + 0.5;
+            let dir = (pcg3df(pixel.extend(17)).x * TAU).direction();
+            let interval = Vec2::new(0.0, 9999.0).expr();
+            let (_fluence, count) = world.trace_counted(pos, dir, interval);
+            steps.write(pixel.x + pixel.y * size.x, count);
+        }));
+
+        trace_kernel.dispatch_blocking([size.x, size.y, 1]);
+        let start = Instant::now();
+        trace_kernel.dispatch_blocking([size.x, size.y, 1]);
+        let elapsed = start.elapsed();
+
+        let steps = steps.copy_to_vec();
+        let total = steps.iter().map(|&x| x as u64).sum::<u64>();
+        let max = steps.iter().copied().max().unwrap_or(0);
+        println!(
+            "{}, {}, {:.2}, {}, {:.3}",
+            B::SIZE,
+            levels,
+            total as f64 / steps.len() as f64,
+            max,
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Time in milliseconds of one call of `dispatch`, averaged over [`TIMED_RUNS`] after a warmup.
+fn time(dispatch: impl Fn()) -> f64 {
+    dispatch();
+    let start = Instant::now();
+    for _ in 0..TIMED_RUNS {
+        dispatch();
+    }
+    start.elapsed().as_secs_f64() * 1000.0 / TIMED_RUNS as f64
+}
+
+/// Traces one ray per pixel through each procedural stress test scene with both tracers,
+/// reporting voxel traversal steps and the time of each tracer.
+pub fn scenes() {
+    let size = Vec2::splat(DISPLAY_SIZE);
+    println!("scene, mean steps, voxel time (ms), analytic objects, analytic time (ms)");
+    for (name, scene) in Scene::stress_tests(0) {
+        let voxels = VoxelTracer::<u64>::new(size);
+        scene.draw(&voxels);
+        let objects = scene.objects();
+        let analytic = AnalyticTracer::new(&objects);
+
+        let steps = DEVICE.create_buffer::<u32>((size.x * size.y) as usize);
+        // Written so the analytic traces aren't optimized away.
+        let radiance = DEVICE.create_buffer::<Radiance>((size.x * size.y) as usize);
+        let voxel_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            let pos = pixel.cast_f32() + 0.5;
+            let dir = (pcg3df(pixel.extend(17)).x * TAU).direction();
+            let (_fluence, count) = voxels.trace_counted(pos, dir, Vec2::new(0.0, 9999.0).expr());
+            steps.write(pixel.x + pixel.y * size.x, count);
+        }));
+        let analytic_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            let pos = pixel.cast_f32() + 0.5;
+            let dir = (pcg3df(pixel.extend(17)).x * TAU).direction();
+            let seed = pcg3d(pixel.extend(18)).x;
+            let traced = analytic.trace(pos, dir, 9999.0.expr(), seed);
+            radiance.write(pixel.x + pixel.y * size.x, traced.fluence.radiance);
+        }));
+        let voxel_time = time(|| voxel_kernel.dispatch_blocking([size.x, size.y, 1]));
+        let analytic_time = time(|| analytic_kernel.dispatch_blocking([size.x, size.y, 1]));
+
+        let steps = steps.copy_to_vec();
+        let total = steps.iter().map(|&x| x as u64).sum::<u64>();
+        println!(
+            "{}, {:.2}, {:.3}, {}, {:.3}",
+            name,
+            total as f64 / steps.len() as f64,
+            voxel_time,
+            objects.len(),
+            analytic_time
+        );
+    }
+}
+
+/// Compares the convergence of the sample sequences on the default scene,
+/// reporting the RMSE against a high sample count reference.
+///
+/// Directions are drawn as in `trace_kernel`, from a fixed random guide mixed with uniform
+/// sampling, so every dimension of the stream shapes the integrand.
+pub fn sampling() {
+    let size = 256;
+    let world = AnalyticTracer::new(&lenses(size as f32));
+    let storage = CascadeStorage::new(Vec2::splat(size), 4, NUM_CASCADES);
+    let guide = (0..storage.data.len() as u32)
+        .map(|i| 0.1 + pcgf_host(i))
+        .collect::<Vec<_>>();
+    storage.data.copy_from(&guide);
+    let accum = DEVICE.create_buffer::<Vec3<f32>>((size * size) as usize);
+    let clear_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+        accum.write(dispatch_id().x, Vec3::splat_expr(0.0_f32));
+    }));
+    let trace_kernel = DEVICE.create_kernel::<fn(u32, u32)>(&track!(|sequence, index| {
+        let pixel = dispatch_id().xy();
+        let pos = pixel.cast_f32() + 0.5;
+        let stream = SampleStream::new(sequence, pixel, index);
+        let (_, bias, dir) = storage.sample_direction(&stream, pos, UNIFORM_FRACTIONS[1].expr());
+        let seed = pcg3d(pixel.extend(index)).x;
+        let segments = storage.trace_segments(&world, pos, dir, seed);
+        let mut radiance = Radiance::splat(0.0).expr();
+        for segment in segments.iter().rev() {
+            radiance = segment.fluence.over_radiance(radiance);
+        }
+        let i = pixel.x + pixel.y * size;
+        accum.write(i, accum.read(i) + radiance / bias);
+    }));
+    let render = |sequence: Sequence, first: u32, samples: u32| {
+        clear_kernel.dispatch([size * size, 1, 1]);
+        for index in first..first + samples {
+            trace_kernel.dispatch([size, size, 1], &(sequence as u32), &index);
+        }
+        accum
+            .copy_to_vec()
+            .into_iter()
+            .map(|x| x.map(|x| x / samples as f32))
+            .collect::<Vec<_>>()
+    };
+    let reference = render(Sequence::Random, 1 << 20, 4096);
+
+    println!("sequence, spp, rmse");
+    for sequence in Sequence::ALL {
+        for samples in [1, 4, 16, 64, 256] {
+            let image = render(sequence, 0, samples);
+            let mse = image
+                .iter()
+                .zip(&reference)
+                .map(|(a, b)| {
+                    let d = [a.x - b.x, a.y - b.y, a.z - b.z];
+                    d.iter().map(|d| (d * d) as f64).sum::<f64>() / 3.0
+                })
+                .sum::<f64>()
+                / image.len() as f64;
+            println!("{:?}, {}, {:.6}", sequence, samples, mse.sqrt());
+        }
+    }
+}
+
+const TIMED_RUNS: u32 = 4;
+const RESOLUTIONS: [u32; 3] = [256, 512, 1024];
+const CASCADE_COUNTS: [u32; 3] = [4, 5, 6];
+
+/// The scenes of [`throughput`], in a world of width 1024.
+fn throughput_scenes() -> Vec<(&'static str, Scene)> {
+    let mut scenes = vec![("sunflower4", Scene::sunflower4()), ("room", Scene::room())];
+    scenes.extend(Scene::stress_tests(0));
+    scenes
+}
+
+/// One row of the [`throughput`] results; `rays` counts paths, not cascade segments.
+struct Measurement<'a> {
+    kernel: &'a str,
+    scene: &'a str,
+    resolution: u32,
+    cascades: Option<u32>,
+    block_size: Option<u32>,
+    rays: u32,
+    time_ms: f64,
+    steps: Option<Vec<u32>>,
+}
+impl Measurement<'_> {
+    const HEADER: &'static str = "kernel,scene,resolution,cascades,block_size,rays,time_ms,rays_per_second,mean_steps,max_steps";
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let optional = |x: Option<u32>| x.map_or(String::new(), |x| x.to_string());
+        let (mean_steps, max_steps) = match &self.steps {
+            Some(steps) => (
+                format!(
+                    "{:.2}",
+                    steps.iter().map(|&x| x as u64).sum::<u64>() as f64 / steps.len() as f64
+                ),
+                optional(steps.iter().copied().max()),
+            ),
+            None => (String::new(), String::new()),
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{:.3},{:.0},{},{}",
+            self.kernel,
+            self.scene,
+            self.resolution,
+            optional(self.cascades),
+            optional(self.block_size),
+            self.rays,
+            self.time_ms,
+            self.rays as f64 / (self.time_ms / 1000.0),
+            mean_steps,
+            max_steps
+        )
+    }
+}
+
+/// Times the trace kernel of a [`Renderer`] with each tracer and number of cascades, and the
+/// voxel tracer alone for each block size, over every scene and resolution.
+/// Results are written as CSV to `path` and echoed to stdout.
+pub fn throughput(path: &str) {
+    let mut out = BufWriter::new(
+        File::create(path).unwrap_or_else(|err| panic!("Failed to create {path}: {err}")),
+    );
+    println!("{}", Measurement::HEADER);
+    writeln!(out, "{}", Measurement::HEADER).unwrap();
+    let mut report = |measurement: Measurement| {
+        measurement.write(&mut std::io::stdout()).unwrap();
+        measurement.write(&mut out).unwrap();
+    };
+    for (name, scene) in throughput_scenes() {
+        for resolution in RESOLUTIONS {
+            let scene = scene.clone().scaled(resolution as f32 / 1024.0);
+            let size = Vec2::splat(resolution);
+            let objects = scene.objects();
+
+            for tracer in [TracerKind::Analytic, TracerKind::Voxel] {
+                for cascades in CASCADE_COUNTS {
+                    let config = Config {
+                        size: resolution,
+                        tracer,
+                        cascades,
+                        ..Config::default()
+                    };
+                    if !config.supports_size(resolution) {
+                        continue;
+                    }
+                    let mut renderer = Renderer::new(&config, &scene, &objects, resolution);
+                    report(Measurement {
+                        kernel: "trace",
+                        scene: name,
+                        resolution,
+                        cascades: Some(cascades),
+                        block_size: (tracer == TracerKind::Voxel).then_some(u64::SIZE),
+                        rays: size.x * size.y,
+                        time_ms: time_iterations(&mut renderer),
+                        steps: None,
+                    });
+                }
+            }
+
+            for block_size in BlockSize::ALL {
+                let measurement = with_block!(block_size, B => voxel_throughput::<B>(&scene, size));
+                report(Measurement {
+                    scene: name,
+                    resolution,
+                    ..measurement
+                });
+            }
+        }
+    }
+    out.flush().unwrap();
+}
+
+/// Time in milliseconds of the trace kernel of one iteration of `renderer`, averaged over
+/// [`TIMED_RUNS`] after a warmup.
+fn time_iterations(renderer: &mut Renderer) -> f64 {
+    let mut learner = Learner::new(Schedule::Cumulative);
+    let mut stats = Stats::new((renderer.size * renderer.size) as u64);
+    let mut iterations = 0;
+    let mut error = None;
+    for run in 0..=TIMED_RUNS {
+        stats.profiling = run > 0;
+        renderer.render_iteration(
+            &mut learner,
+            &mut stats,
+            &mut iterations,
+            &mut error,
+            Sequence::Random,
+            UNIFORM_FRACTIONS[1],
+        );
+    }
+    stats.mean_time("trace_kernel").unwrap().as_secs_f64() * 1000.0
+}
+
+fn voxel_throughput<B: Block>(scene: &Scene, size: Vec2<u32>) -> Measurement<'static> {
+    let world = VoxelTracer::<B>::new(size);
+    scene.draw(&world);
+    let steps = DEVICE.create_buffer::<u32>((size.x * size.y) as usize);
+    let trace_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+        let pixel = dispatch_id().xy();
+        let pos = pixel.cast_f32() + 0.5;
+        let dir = (pcg3df(pixel.extend(17)).x * TAU).direction();
+        let (_fluence, count) = world.trace_counted(pos, dir, Vec2::new(0.0, 9999.0).expr());
+        steps.write(pixel.x + pixel.y * size.x, count);
+    }));
+    Measurement {
+        kernel: "voxel_trace",
+        scene: "",
+        resolution: size.x,
+        cascades: None,
+        block_size: Some(B::SIZE),
+        rays: size.x * size.y,
+        time_ms: time(|| trace_kernel.dispatch_blocking([size.x, size.y, 1])),
+        steps: Some(steps.copy_to_vec()),
+    }
+}
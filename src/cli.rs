@@ -0,0 +1,433 @@
+use std::path::PathBuf;
+
+use super::*;
+
+pub const USAGE: &str = "\
+Usage: vlam [options]
+       vlam bench-occupancy | bench-sampling | bench-scenes | bench-throughput [path]
+
+Options:
+  --size <pixels>            Resolution of the square render, a power of two from 16 to 4096 [1024]
+  --iterations <count>       Iterations to render before stopping [1000]
+  --scene <name or path>     Built-in scene, or a scene file [lenses]
+                             Built-in: lenses, simple, room, pinhole, sunflower4,
+                             circle_field, maze, fog, emitters
+  --tracer <analytic|voxel>  Tracer used for the guided paths [analytic]
+                             Edits only apply to the analytic tracer.
+  --light-tracing <off|only|combined>
+                             Light tracing estimate shown at launch, analytic tracer
+                             and window only [off]
+  --cascades <count>         Number of cascades of the guide, from 1 to 6 [6]
+  --base-angles <count>      Directions per probe of the first cascade, from 1 to 64 [4]
+  --uniform-fraction <f>     Probability of sampling uniformly instead of from the guide [0.1]
+  --training <iterations>    Guide training iterations before rendering [0]
+  --seed <seed>              Seed of the procedural scenes [0]
+  --output <path.pfm>        Where to write the rendered radiance when done
+  --aov <name>               Also write a diagnostic output next to --output, as
+                             <path>.<name>.pfm; may be repeated. One of transmittance,
+                             length, steps, refractions, leaf, bias
+  --guide <path>             Guide to start from if it matches the scene and cascades,
+                             and to save to when done
+  --denoise                  Denoise the display and the radiance written to --output
+  --headless                 Render without a window; requires --output
+  --help                     Show this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TracerKind {
+    Analytic,
+    Voxel,
+}
+
+/// Launch options; the defaults are what the renderer used before they could be set, except for
+/// `uniform_fraction`, which defaults to a tenth of uniform samples to bound fireflies where the
+/// guide is poorly learned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub size: u32,
+    pub iterations: u32,
+    pub scene: String,
+    pub tracer: TracerKind,
+    pub light_tracing: LightTracing,
+    pub cascades: u32,
+    pub base_angles: u32,
+    pub uniform_fraction: f32,
+    pub training_iterations: u32,
+    pub seed: u32,
+    pub output: Option<PathBuf>,
+    /// Diagnostic outputs written along with `output`.
+    pub aovs: Vec<Aov>,
+    pub guide: Option<PathBuf>,
+    pub denoise: bool,
+    pub headless: bool,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            size: DISPLAY_SIZE,
+            iterations: MAX_ITERS,
+            scene: "lenses".to_string(),
+            tracer: TracerKind::Analytic,
+            light_tracing: LightTracing::Off,
+            cascades: NUM_CASCADES,
+            base_angles: 4,
+            uniform_fraction: UNIFORM_FRACTIONS[1],
+            training_iterations: TRAINING_ITERATIONS[0],
+            seed: 0,
+            output: None,
+            aovs: vec![],
+            guide: None,
+            denoise: false,
+            headless: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    Help,
+    Invalid(String),
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError::Invalid(format!("{flag} expects a number, got `{value}`")))
+}
+
+fn check(ok: bool, message: impl FnOnce() -> String) -> Result<(), CliError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(CliError::Invalid(message()))
+    }
+}
+
+impl Config {
+    /// Parses the arguments after the program name; flags take their value either as the next
+    /// argument or after `=`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            match flag.as_str() {
+                "--help" | "-h" => return Err(CliError::Help),
+                "--headless" => {
+                    check(inline.is_none(), || "--headless takes no value".to_string())?;
+                    config.headless = true;
+                    continue;
+                }
+                "--denoise" => {
+                    check(inline.is_none(), || "--denoise takes no value".to_string())?;
+                    config.denoise = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = match inline.or_else(|| args.next()) {
+                Some(value) => value,
+                None if flag.starts_with("--") => {
+                    return Err(CliError::Invalid(format!("{flag} expects a value")));
+                }
+                None => return Err(CliError::Invalid(format!("unexpected argument `{flag}`"))),
+            };
+            match flag.as_str() {
+                "--size" => config.size = number(&flag, &value)?,
+                "--iterations" => config.iterations = number(&flag, &value)?,
+                "--scene" => config.scene = value,
+                "--tracer" => {
+                    config.tracer = match value.as_str() {
+                        "analytic" => TracerKind::Analytic,
+                        "voxel" => TracerKind::Voxel,
+                        _ => {
+                            return Err(CliError::Invalid(format!(
+                                "--tracer expects `analytic` or `voxel`, got `{value}`"
+                            )));
+                        }
+                    }
+                }
+                "--light-tracing" => {
+                    config.light_tracing = match value.as_str() {
+                        "off" => LightTracing::Off,
+                        "only" => LightTracing::Only,
+                        "combined" => LightTracing::Combined,
+                        _ => {
+                            return Err(CliError::Invalid(format!(
+                                "--light-tracing expects `off`, `only` or `combined`, got `{value}`"
+                            )));
+                        }
+                    }
+                }
+                "--cascades" => config.cascades = number(&flag, &value)?,
+                "--base-angles" => config.base_angles = number(&flag, &value)?,
+                "--uniform-fraction" => config.uniform_fraction = number(&flag, &value)?,
+                "--training" => config.training_iterations = number(&flag, &value)?,
+                "--seed" => config.seed = number(&flag, &value)?,
+                "--output" => config.output = Some(PathBuf::from(value)),
+                "--aov" => match Aov::from_name(&value).filter(|&aov| aov != Aov::Radiance) {
+                    Some(aov) => config.aovs.push(aov),
+                    None => return Err(CliError::Invalid(format!("unknown AOV `{value}`"))),
+                },
+                "--guide" => config.guide = Some(PathBuf::from(value)),
+                _ => return Err(CliError::Invalid(format!("unknown option `{flag}`"))),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+    fn validate(&self) -> Result<(), CliError> {
+        check(
+            self.size.is_power_of_two() && (MIN_SIZE..=MAX_SIZE).contains(&self.size),
+            || {
+                format!(
+                    "--size must be a power of two from {MIN_SIZE} to {MAX_SIZE}, got {}",
+                    self.size
+                )
+            },
+        )?;
+        check(self.iterations > 0, || {
+            "--iterations must be at least 1".to_string()
+        })?;
+        check((1..=6).contains(&self.cascades), || {
+            format!("--cascades must be from 1 to 6, got {}", self.cascades)
+        })?;
+        check((1..=64).contains(&self.base_angles), || {
+            format!(
+                "--base-angles must be from 1 to 64, got {}",
+                self.base_angles
+            )
+        })?;
+        // The probes of the last cascade are spaced `1 << (cascades - 1)` pixels apart.
+        check(self.size >= 1 << (self.cascades - 1), || {
+            format!(
+                "--size {} is too small for {} cascades, which need at least {} pixels",
+                self.size,
+                self.cascades,
+                1 << (self.cascades - 1)
+            )
+        })?;
+        let entries = self.guide_entries(self.size);
+        check(entries <= MAX_GUIDE_ENTRIES, || {
+            format!(
+                "the guide would have {entries} weights, more than the {MAX_GUIDE_ENTRIES} it can \
+                 hold; lower --size, --base-angles or --cascades"
+            )
+        })?;
+        check((0.0..=1.0).contains(&self.uniform_fraction), || {
+            format!(
+                "--uniform-fraction must be from 0 to 1, got {}",
+                self.uniform_fraction
+            )
+        })?;
+        if let Some(output) = &self.output {
+            check(
+                output
+                    .extension()
+                    .is_some_and(|extension| extension == "pfm"),
+                || format!("--output must be a .pfm file, got {}", output.display()),
+            )?;
+        }
+        check(self.aovs.is_empty() || self.output.is_some(), || {
+            "--aov requires --output".to_string()
+        })?;
+        check(!self.headless || self.output.is_some(), || {
+            "--headless requires --output, or the render would be discarded".to_string()
+        })?;
+        // Light paths are traced through the analytic objects only, and only shown in the window.
+        let light_tracing = self.light_tracing != LightTracing::Off;
+        check(
+            !light_tracing || self.tracer == TracerKind::Analytic,
+            || "--light-tracing only sees the analytic objects, not --tracer voxel".to_string(),
+        )?;
+        check(!light_tracing || !self.headless, || {
+            "--light-tracing is only shown in the window, not saved by --headless".to_string()
+        })
+    }
+    /// Weights of the guide at a render resolution of `size`.
+    fn guide_entries(&self, size: u32) -> u64 {
+        size as u64 * size as u64 * self.base_angles as u64 * self.cascades as u64
+    }
+    /// Whether the render resolution can be changed to the power of two `size` at runtime, by the
+    /// same limits as `--size`.
+    pub fn supports_size(&self, size: u32) -> bool {
+        (MIN_SIZE..=MAX_SIZE).contains(&size)
+            && size >= 1 << (self.cascades - 1)
+            && self.guide_entries(size) <= MAX_GUIDE_ENTRIES
+    }
+    /// Fails if any of the images to write can't be, so a long render isn't discarded: each must go
+    /// in an existing, writable directory, and not replace a read-only file. Nothing is created.
+    pub fn check_outputs(&self) -> Result<(), String> {
+        let aovs = self.aovs.iter().filter_map(|&aov| self.aov_path(aov));
+        for path in self.output.iter().cloned().chain(aovs) {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let error = |reason: String| format!("can't write {}: {reason}", path.display());
+            let dir_metadata =
+                std::fs::metadata(dir).map_err(|err| error(format!("{}: {err}", dir.display())))?;
+            if !dir_metadata.is_dir() || dir_metadata.permissions().readonly() {
+                return Err(error(format!(
+                    "{} is not a writable directory",
+                    dir.display()
+                )));
+            }
+            if let Ok(metadata) = std::fs::metadata(&path)
+                && (metadata.is_dir() || metadata.permissions().readonly())
+            {
+                return Err(error("it is a directory or read-only".to_string()));
+            }
+        }
+        Ok(())
+    }
+    /// Where `aov` is written, next to the output.
+    pub fn aov_path(&self, aov: Aov) -> Option<PathBuf> {
+        let output = self.output.as_ref()?;
+        Some(output.with_extension(format!("{}.pfm", aov.name())))
+    }
+    /// The selected scene, scaled to the render size.
+    pub fn scene(&self) -> Result<Scene, String> {
+        let scene = match Scene::named(&self.scene, self.seed) {
+            Some(scene) => scene,
+            None => {
+                let text = std::fs::read_to_string(&self.scene).map_err(|err| {
+                    format!(
+                        "`{}` is neither a built-in scene nor a readable file: {err}",
+                        self.scene
+                    )
+                })?;
+                Scene::parse(&text).map_err(|err| format!("{}: {err}", self.scene))?
+            }
+        };
+        Ok(scene.scaled(self.size as f32 / 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::library;
+
+    fn parse(args: &[&str]) -> Result<Config, CliError> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn invalid(args: &[&str]) -> String {
+        match parse(args) {
+            Err(CliError::Invalid(message)) => message,
+            other => panic!("expected an error for {args:?}, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn no_arguments_give_defaults() {
+        assert_eq!(parse(&[]), Ok(Config::default()));
+    }
+
+    #[test]
+    fn flags_take_separate_or_inline_values() {
+        let config = parse(&[
+            "--size",
+            "256",
+            "--tracer=voxel",
+            "--cascades",
+            "4",
+            "--headless",
+            "--denoise",
+            "--output=out.pfm",
+            "--aov",
+            "steps",
+            "--aov=bias",
+        ])
+        .unwrap();
+        assert_eq!(config.size, 256);
+        assert_eq!(config.tracer, TracerKind::Voxel);
+        assert_eq!(config.cascades, 4);
+        assert!(config.headless);
+        assert!(config.denoise);
+        assert_eq!(config.output, Some(PathBuf::from("out.pfm")));
+        assert_eq!(config.aovs, vec![Aov::Steps, Aov::Bias]);
+        assert_eq!(
+            config.aov_path(Aov::Steps),
+            Some(PathBuf::from("out.steps.pfm"))
+        );
+    }
+
+    #[test]
+    fn invalid_arguments_are_explained() {
+        assert!(invalid(&["--size", "300"]).contains("power of two"));
+        assert!(invalid(&["--size", "big"]).contains("expects a number"));
+        assert!(invalid(&["--cascades", "7"]).contains("from 1 to 6"));
+        assert!(invalid(&["--tracer", "mesh"]).contains("`analytic` or `voxel`"));
+        assert!(invalid(&["--light-tracing", "on"]).contains("`off`, `only` or `combined`"));
+        assert!(
+            invalid(&["--light-tracing", "only", "--tracer", "voxel"]).contains("--tracer voxel")
+        );
+        let headless = [
+            "--light-tracing=combined",
+            "--headless",
+            "--output",
+            "out.pfm",
+        ];
+        assert!(invalid(&headless).contains("not saved by --headless"));
+        assert!(invalid(&["--headless"]).contains("requires --output"));
+        assert!(invalid(&["--output", "out.png"]).contains(".pfm"));
+        assert!(invalid(&["--aov", "steps"]).contains("requires --output"));
+        assert!(invalid(&["--aov", "radiance"]).contains("unknown AOV"));
+        assert!(invalid(&["--iterations"]).contains("expects a value"));
+        assert!(invalid(&["--size", "16"]).contains("too small for 6 cascades"));
+        assert!(parse(&["--size", "16", "--cascades", "5"]).is_ok());
+        let huge = ["--size", "4096", "--base-angles", "64"];
+        assert!(invalid(&huge).contains("lower --size"));
+        assert!(invalid(&["--frobnicate", "1"]).contains("unknown option"));
+        assert_eq!(parse(&["--size", "64", "--help"]), Err(CliError::Help));
+    }
+
+    #[test]
+    fn runtime_sizes_follow_the_same_limits() {
+        let config = parse(&["--size", "64", "--cascades", "6", "--base-angles", "64"]).unwrap();
+        assert!(!config.supports_size(16));
+        assert!(config.supports_size(32));
+        assert!(config.supports_size(1024));
+        assert!(!config.supports_size(4096));
+    }
+
+    #[test]
+    fn scene_files_parse() {
+        let scene = Scene::parse(
+            "# A light and a wall\n\
+             circle 100 200 5 light\n\
+             \n\
+             rect 512 512 8 64.5 frosted glass\n",
+        )
+        .unwrap();
+        assert_eq!(scene.draws.len(), 2);
+        assert!(matches!(scene.draws[1].brush, Brush::Rect(8.0, 64.5)));
+        assert_eq!(scene.draws[1].material, library()[1].1);
+
+        let error = Scene::parse("circle 1 2 3 light\ncircle 1 2 marble").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{error}");
+        let error = Scene::parse("circle 1 2 3 marble").unwrap_err();
+        assert!(error.contains("unknown material `marble`"), "{error}");
+    }
+
+    #[test]
+    fn named_scenes_exist() {
+        for name in [
+            "lenses",
+            "simple",
+            "room",
+            "pinhole",
+            "sunflower4",
+            "circle_field",
+            "maze",
+            "fog",
+            "emitters",
+        ] {
+            assert!(Scene::named(name, 0).is_some(), "{name}");
+        }
+    }
+}
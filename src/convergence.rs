@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use super::*;
+
+/// Relative error targets cycled through at runtime.
+pub const TARGET_ERRORS: [Option<f32>; 5] = [None, Some(0.1), Some(0.05), Some(0.02), Some(0.01)];
+/// Time budgets cycled through at runtime.
+pub const TIME_BUDGETS: [Option<Duration>; 4] = [
+    None,
+    Some(Duration::from_secs(10)),
+    Some(Duration::from_secs(30)),
+    Some(Duration::from_secs(120)),
+];
+
+/// Rendering stops as soon as any of the set limits is reached.
+#[derive(Debug, Clone, Copy)]
+pub struct StoppingRule {
+    pub max_iterations: Option<u32>,
+    pub time_budget: Option<Duration>,
+    /// Mean per-pixel relative standard error of the display estimate.
+    pub target_error: Option<f32>,
+}
+impl StoppingRule {
+    pub fn should_stop(&self, iterations: u32, elapsed: Duration, error: Option<f32>) -> bool {
+        self.max_iterations.is_some_and(|max| iterations >= max)
+            || self.time_budget.is_some_and(|budget| elapsed >= budget)
+            || self
+                .target_error
+                .zip(error)
+                .is_some_and(|(target, error)| error <= target)
+    }
+    pub fn cycle_target_error(&mut self) {
+        let i = TARGET_ERRORS.iter().position(|&x| x == self.target_error);
+        self.target_error = TARGET_ERRORS[i.map_or(0, |i| (i + 1) % TARGET_ERRORS.len())];
+        println!("Target error: {:?}", self.target_error);
+    }
+    pub fn cycle_time_budget(&mut self) {
+        let i = TIME_BUDGETS.iter().position(|&x| x == self.time_budget);
+        self.time_budget = TIME_BUDGETS[i.map_or(0, |i| (i + 1) % TIME_BUDGETS.len())];
+        println!("Time budget: {:?}", self.time_budget);
+    }
+}
+
+/// Per-pixel counts and second moments of the estimates added to the display, one per iteration,
+/// for normalizing it and estimating its noise.
+pub struct Convergence {
+    /// Samples traced into the display, which number the next ones.
+    pub samples: Tex2d<u32>,
+    pub estimates: Tex2d<u32>,
+    pub second_moment: Tex2d<f32>,
+    error_sum: Buffer<f32>,
+    pixels: u32,
+}
+impl Convergence {
+    pub fn new(size: Vec2<u32>) -> Self {
+        Self {
+            samples: DEVICE.create_tex2d(PixelStorage::Int1, size.x, size.y, 1),
+            estimates: DEVICE.create_tex2d(PixelStorage::Int1, size.x, size.y, 1),
+            second_moment: DEVICE.create_tex2d(PixelStorage::Float1, size.x, size.y, 1),
+            error_sum: DEVICE.create_buffer_from_fn(1, |_| 0.0),
+            pixels: size.x * size.y,
+        }
+    }
+    #[tracked]
+    pub fn clear(&self, pixel: Expr<Vec2<u32>>) {
+        self.samples.write(pixel, 0);
+        self.estimates.write(pixel, 0);
+        self.second_moment.write(pixel, 0.0);
+    }
+    /// Records an iteration's `estimate` at `pixel`, the mean of the `samples` it traced.
+    #[tracked]
+    pub fn record(&self, pixel: Expr<Vec2<u32>>, estimate: Expr<Radiance>, samples: Expr<u32>) {
+        self.samples
+            .write(pixel, self.samples.read(pixel) + samples);
+        self.estimates.write(pixel, self.estimates.read(pixel) + 1);
+        self.second_moment
+            .write(pixel, self.second_moment.read(pixel) + luma(estimate).sqr());
+    }
+    /// Mean of the estimates at `pixel`, given their `sum`.
+    #[tracked]
+    pub fn mean(&self, pixel: Expr<Vec2<u32>>, sum: Expr<Radiance>) -> Expr<Radiance> {
+        sum / keter::max(self.estimates.read(pixel), 1).cast_f32()
+    }
+    /// Relative standard error of [`Self::mean`] at `pixel`.
+    #[tracked]
+    pub fn relative_error(&self, pixel: Expr<Vec2<u32>>, sum: Expr<Radiance>) -> Expr<f32> {
+        let n = keter::max(self.estimates.read(pixel), 1).cast_f32();
+        let mean = luma(sum) / n;
+        let variance = keter::max(self.second_moment.read(pixel) / n - mean.sqr(), 0.0) * n
+            / keter::max(n - 1.0, 1.0);
+        // The epsilon keeps black pixels from dominating.
+        (variance / n).sqrt() / (mean + 0.001)
+    }
+    #[tracked]
+    pub fn accumulate_error(&self, pixel: Expr<Vec2<u32>>, sum: Expr<Radiance>) {
+        self.error_sum
+            .atomic_fetch_add(0, self.relative_error(pixel, sum) / self.pixels as f32);
+    }
+    /// Mean relative error over all pixels, as accumulated by [`Self::accumulate_error`].
+    /// Resets the accumulator.
+    pub fn take_mean_error(&self) -> f32 {
+        let error = self.error_sum.copy_to_vec()[0];
+        self.error_sum.copy_from(&[0.0]);
+        error
+    }
+}
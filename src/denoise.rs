@@ -0,0 +1,165 @@
+//! Edge-aware denoising of the display with an à-trous wavelet filter (Dammertz et al. 2010,
+//! "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering").
+//!
+//! Neighbors are weighted down where the world's emission or opacity differ, so object
+//! boundaries stay sharp, and where their luminance differs by more than the noise of the
+//! estimate, so converged pixels are left alone.
+
+use super::*;
+
+/// Filter passes, each doubling the spacing of the taps; five reach 62 pixels out.
+pub const PASSES: u32 = 5;
+/// B3-spline weights of the taps at offsets 0, 1 and 2.
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Squared difference of the features at which a neighbor's weight falls to `1 / e`.
+const FEATURE_SIGMA: f32 = 0.01;
+/// Luminance difference, in standard errors of the estimate, at which a neighbor's weight falls
+/// to `1 / e`.
+const LUMA_SIGMA: f32 = 4.0;
+
+pub struct Denoiser {
+    /// Emission of the world, compressed to `[0, 1)`.
+    emission: Tex2d<Vec3<f32>>,
+    /// Transmittance of the world across one pixel.
+    transmittance: Tex2d<Vec3<f32>>,
+    /// Standard error of the luminance of the undenoised estimate.
+    deviation: Tex2d<f32>,
+    /// Ping-pong buffers of the passes, starting with the undenoised estimate in the first.
+    buffers: [Tex2d<Vec3<f32>>; 2],
+    size: Vec2<u32>,
+    pub enabled: bool,
+}
+impl Denoiser {
+    pub fn new(size: Vec2<u32>) -> Self {
+        let tex = || DEVICE.create_tex2d::<Vec3<f32>>(PixelStorage::Float4, size.x, size.y, 1);
+        Self {
+            emission: tex(),
+            transmittance: tex(),
+            deviation: DEVICE.create_tex2d(PixelStorage::Float1, size.x, size.y, 1),
+            buffers: [tex(), tex()],
+            size,
+            enabled: false,
+        }
+    }
+    pub fn memory_bytes(&self) -> usize {
+        (self.size.x * self.size.y) as usize
+            * (4 * std::mem::size_of::<Vec4<f32>>() + std::mem::size_of::<f32>())
+    }
+    /// Records the features of `material` at `pixel`, which must be redone when the world changes.
+    #[tracked]
+    pub fn write_features(&self, pixel: Expr<Vec2<u32>>, material: Expr<Material>) {
+        let color = material.color();
+        self.emission
+            .write(pixel, color.emission / (1.0 + color.emission));
+        self.transmittance.write(pixel, (-color.opacity).exp());
+    }
+    /// Sets the estimate to denoise at `pixel`, with the standard error of its luminance.
+    #[tracked]
+    pub fn write_input(&self, pixel: Expr<Vec2<u32>>, mean: Expr<Radiance>, deviation: Expr<f32>) {
+        self.buffers[0].write(pixel, mean);
+        self.deviation.write(pixel, deviation);
+    }
+    /// Filter pass `pass` at `pixel`, reading the previous pass's buffer and writing the other.
+    #[tracked]
+    pub fn filter(&self, pixel: Expr<Vec2<u32>>, pass: u32) {
+        let src = &self.buffers[pass as usize % 2];
+        let dst = &self.buffers[(pass as usize + 1) % 2];
+        let step = (1_i32 << pass).expr();
+        let color = src.read(pixel);
+        let emission = self.emission.read(pixel);
+        let transmittance = self.transmittance.read(pixel);
+        let luma_scale = LUMA_SIGMA * self.deviation.read(pixel) + 1e-4;
+        let size = Vec2::new(self.size.x as i32, self.size.y as i32).expr();
+
+        let sum = Vec3::splat_expr(0.0_f32).var();
+        let total = 0.0_f32.var();
+        for dy in (-2_i32..=2) {
+            for dx in (-2_i32..=2) {
+                let h = KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                let q = pixel.cast_i32() + Vec2::expr(step * dx, step * dy);
+                if (q >= 0).all() && (q < size).all() {
+                    let q = q.cast_u32();
+                    let neighbor = src.read(q);
+                    let emission_diff = self.emission.read(q) - emission;
+                    let transmittance_diff = self.transmittance.read(q) - transmittance;
+                    let feature_distance = emission_diff.dot(emission_diff)
+                        + transmittance_diff.dot(transmittance_diff);
+                    let luma_distance = (luma(neighbor) - luma(color)).abs();
+                    let w =
+                        h * (-feature_distance / FEATURE_SIGMA - luma_distance / luma_scale).exp();
+                    *sum += neighbor * w;
+                    *total += w;
+                }
+            }
+        }
+        dst.write(pixel, **sum / **total);
+    }
+    /// The buffer holding the result of the last pass.
+    pub fn output(&self) -> &Tex2d<Vec3<f32>> {
+        &self.buffers[PASSES as usize % 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::pcgf_host;
+
+    #[test]
+    fn denoising_smooths_noise_but_not_edges() {
+        let size = 32;
+        let denoiser = Denoiser::new(Vec2::splat(size));
+        let light = Material {
+            emission: Vec3::splat(1.0),
+            ..Material::empty()
+        };
+        let noise =
+            DEVICE.create_buffer_from_fn((size * size) as usize, |i| pcgf_host(i as u32) - 0.5);
+        // A noisy light on the left and noisy empty space on the right, with a deviation so large
+        // that only the features keep them apart.
+        DEVICE
+            .create_kernel::<fn()>(&track!(|| {
+                let pixel = dispatch_id().xy();
+                let noise = Vec3::splat_expr(noise.read(pixel.x + pixel.y * size));
+                let deviation = 100.0_f32.expr();
+                if pixel.x < size / 2 {
+                    denoiser.write_features(pixel, light.expr());
+                    denoiser.write_input(pixel, noise + 1.0, deviation);
+                } else {
+                    denoiser.write_features(pixel, Material::empty().expr());
+                    denoiser.write_input(pixel, noise, deviation);
+                }
+            }))
+            .dispatch([size, size, 1]);
+        for pass in 0..PASSES {
+            DEVICE
+                .create_kernel::<fn()>(&track!(|| {
+                    denoiser.filter(dispatch_id().xy(), pass);
+                }))
+                .dispatch([size, size, 1]);
+        }
+        let output = DEVICE.create_buffer::<f32>((size * size) as usize);
+        DEVICE
+            .create_kernel::<fn()>(&track!(|| {
+                let pixel = dispatch_id().xy();
+                output.write(pixel.x + pixel.y * size, denoiser.output().read(pixel).x);
+            }))
+            .dispatch([size, size, 1]);
+
+        let noise = noise.copy_to_vec();
+        let output = output.copy_to_vec();
+        for half in [0, 1] {
+            let expected = if half == 0 { 1.0 } else { 0.0 };
+            let pixels = (0..size * size).filter(|i| (i % size >= size / 2) as u32 == half);
+            let error = |values: &[f32], offset: f32| {
+                pixels
+                    .clone()
+                    .map(|i| (values[i as usize] + offset - expected).powi(2))
+                    .sum::<f32>()
+            };
+            let before = error(&noise, expected);
+            let after = error(&output, 0.0);
+            assert!(after < before / 10.0, "half {half}: {after} vs {before}");
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::Write;
+use std::path::Path;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Tonemapper {
+    AgX,
+    Aces,
+    Reinhard,
+    Linear,
+}
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 4] = [
+        Tonemapper::AgX,
+        Tonemapper::Aces,
+        Tonemapper::Reinhard,
+        Tonemapper::Linear,
+    ];
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Runtime display controls; the app applies no tonemapping of its own.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplaySettings {
+    /// Exposure compensation in stops, applied on top of auto exposure if enabled.
+    pub exposure_ev: f32,
+    pub tonemapper: Tonemapper,
+    pub auto_exposure: bool,
+    pub false_color: bool,
+}
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            exposure_ev: 0.0,
+            tonemapper: Tonemapper::AgX,
+            auto_exposure: false,
+            false_color: false,
+        }
+    }
+}
+impl DisplaySettings {
+    /// Linear exposure multiplier applied before tonemapping.
+    pub fn exposure(&self, histogram: &Histogram) -> f32 {
+        let base = if self.auto_exposure {
+            histogram.auto_exposure()
+        } else {
+            1.0
+        };
+        base * self.exposure_ev.exp2()
+    }
+}
+
+// Minimal AgX fit from https://iolite-engine.com/blog_posts/minimal_agx_implementation
+#[tracked]
+fn agx(color: Expr<Vec3<f32>>) -> Expr<Vec3<f32>> {
+    let min_ev = -12.47393_f32;
+    let max_ev = 4.026069_f32;
+    let x = Vec3::expr(
+        color.dot(Vec3::expr(
+            0.842479062253094,
+            0.0784335999999992,
+            0.0792237451477643,
+        )),
+        color.dot(Vec3::expr(
+            0.0423282422610123,
+            0.878468636469772,
+            0.0791661274605434,
+        )),
+        color.dot(Vec3::expr(0.0423756549057051, 0.0784336, 0.879142973793104)),
+    );
+    let x = keter::max(x, Vec3::splat_expr(1e-10_f32)).log2();
+    let x = ((x - min_ev) / (max_ev - min_ev)).clamp(0.0, 1.0);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let x =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+    let x = Vec3::expr(
+        x.dot(Vec3::expr(
+            1.19687900512017,
+            -0.0980208811401368,
+            -0.0990297440797205,
+        )),
+        x.dot(Vec3::expr(
+            -0.0528968517574562,
+            1.15190312990417,
+            -0.0989611768448433,
+        )),
+        x.dot(Vec3::expr(
+            -0.0529716355144438,
+            -0.0980434501171241,
+            1.15107367264116,
+        )),
+    );
+    // The fit outputs sRGB-encoded values.
+    keter::max(x, Vec3::splat_expr(0.0_f32)).powf(2.2)
+}
+
+// Narkowicz 2015, "ACES Filmic Tone Mapping Curve"
+#[tracked]
+fn aces(color: Expr<Vec3<f32>>) -> Expr<Vec3<f32>> {
+    let x = color * 0.6;
+    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+#[tracked]
+pub fn tonemap(color: Expr<Vec3<f32>>, tonemapper: Expr<u32>) -> Expr<Vec3<f32>> {
+    if tonemapper == Tonemapper::AgX as u32 {
+        agx(color)
+    } else if tonemapper == Tonemapper::Aces as u32 {
+        aces(color)
+    } else if tonemapper == Tonemapper::Reinhard as u32 {
+        color / (1.0 + luma(color))
+    } else {
+        color.clamp(0.0, 1.0)
+    }
+}
+
+// Polynomial fit of the Turbo colormap, https://www.shadertoy.com/view/3lBXR3
+#[tracked]
+fn turbo(t: Expr<f32>) -> Expr<Vec3<f32>> {
+    let t = t.clamp(0.0, 1.0);
+    let c0 = Vec3::expr(0.1140890109226559, 0.06288340699912215, 0.2248337216805064);
+    let c1 = Vec3::expr(6.716419496985708, 3.182286745507602, 7.571581586103393);
+    let c2 = Vec3::expr(-66.09402360453038, -4.9279827041226, -10.09439367561635);
+    let c3 = Vec3::expr(228.7660791526501, 25.04986699771073, -91.54105330182436);
+    let c4 = Vec3::expr(-334.8351565777451, -69.31749712757485, 288.5858850615712);
+    let c5 = Vec3::expr(218.7637218434795, 67.52150567819112, -305.2045772184957);
+    let c6 = Vec3::expr(-52.88903478218835, -21.54527364654712, 110.5174647748972);
+    let srgb = c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+    keter::max(srgb, Vec3::splat_expr(0.0_f32)).powf(2.2)
+}
+
+const FALSE_COLOR_MIN_EV: f32 = -10.0;
+const FALSE_COLOR_MAX_EV: f32 = 6.0;
+
+/// Log-luminance heatmap, from `FALSE_COLOR_MIN_EV` (blue) to `FALSE_COLOR_MAX_EV` (red).
+#[tracked]
+pub fn false_color(color: Expr<Vec3<f32>>) -> Expr<Vec3<f32>> {
+    let ev = keter::max(luma(color), 1e-10_f32.expr()).log2();
+    turbo((ev - FALSE_COLOR_MIN_EV) / (FALSE_COLOR_MAX_EV - FALSE_COLOR_MIN_EV))
+}
+
+const HISTOGRAM_BINS: u32 = 64;
+const HISTOGRAM_MIN_EV: f32 = -16.0;
+const HISTOGRAM_MAX_EV: f32 = 16.0;
+
+/// Log-luminance histogram of the display, used for auto exposure.
+pub struct Histogram {
+    pub bins: Buffer<u32>,
+}
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            bins: DEVICE.create_buffer_from_fn(HISTOGRAM_BINS as usize, |_| 0),
+        }
+    }
+    pub fn clear(&self) {
+        self.bins.copy_from(&[0; HISTOGRAM_BINS as usize]);
+    }
+    #[tracked]
+    pub fn add(&self, color: Expr<Vec3<f32>>) {
+        let lum = luma(color);
+        // Pure black pixels would drag the average down, so they are skipped.
+        if lum > 0.0 {
+            let t = (lum.log2() - HISTOGRAM_MIN_EV) / (HISTOGRAM_MAX_EV - HISTOGRAM_MIN_EV);
+            let bin = (t * HISTOGRAM_BINS as f32).clamp(0.0, (HISTOGRAM_BINS - 1) as f32);
+            self.bins.atomic_fetch_add(bin.cast_u32(), 1);
+        }
+    }
+    /// Exposure mapping the mean log luminance to middle gray,
+    /// ignoring the darkest and brightest few percent.
+    pub fn auto_exposure(&self) -> f32 {
+        let bins = self.bins.copy_to_vec();
+        let total = bins.iter().map(|&x| x as u64).sum::<u64>();
+        if total == 0 {
+            return 1.0;
+        }
+        let [low, high] = [0.05, 0.95].map(|q| (q * total as f64) as u64);
+        let mut seen = 0;
+        let mut sum = 0.0;
+        let mut count = 0;
+        for (i, &n) in bins.iter().enumerate() {
+            let n = n as u64;
+            let kept = (seen + n).min(high).saturating_sub(seen.max(low));
+            seen += n;
+            let ev = HISTOGRAM_MIN_EV
+                + (i as f32 + 0.5) / HISTOGRAM_BINS as f32 * (HISTOGRAM_MAX_EV - HISTOGRAM_MIN_EV);
+            sum += kept as f64 * ev as f64;
+            count += kept;
+        }
+        if count == 0 {
+            return 1.0;
+        }
+        0.18 / (sum / count as f64).exp2() as f32
+    }
+}
+
+/// Writes row-major, top to bottom radiance as a little-endian PFM image.
+pub fn write_pfm(path: &Path, size: Vec2<u32>, radiance: &[Radiance]) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write!(out, "PF\n{} {}\n-1.0\n", size.x, size.y)?;
+    // PFM rows go from bottom to top.
+    for row in radiance.chunks(size.x as usize).rev() {
+        for pixel in row {
+            for c in [pixel.x, pixel.y, pixel.z] {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
@@ -0,0 +1,106 @@
+use super::*;
+use crate::material::library;
+
+/// Interface roughnesses cycled through when editing an object.
+pub const ROUGHNESSES: [f32; 4] = [0.0, 0.1, 0.3, 0.6];
+
+/// Host-side copy of the analytic scene, edited interactively and uploaded on change.
+pub struct ObjectEditor {
+    pub objects: Vec<Object>,
+    pub selected: Option<usize>,
+    drag_offset: Vec2<f32>,
+    material_index: usize,
+}
+impl ObjectEditor {
+    pub fn new(objects: Vec<Object>) -> Self {
+        Self {
+            objects,
+            selected: None,
+            drag_offset: Vec2::splat(0.0),
+            material_index: 0,
+        }
+    }
+    /// Scales all positions and radii by `factor`, e.g. to follow a change of resolution.
+    pub fn rescale(&mut self, factor: f32) {
+        for object in &mut self.objects {
+            object.center = object.center * factor;
+            object.radius *= factor;
+        }
+        self.drag_offset = self.drag_offset * factor;
+    }
+    /// Selects the topmost object under `cursor`, or deselects if there is none.
+    pub fn select(&mut self, cursor: Vec2<f32>) {
+        self.selected = self.objects.iter().rposition(|object| {
+            let delta = cursor - object.center;
+            delta.x * delta.x + delta.y * delta.y < object.radius * object.radius
+        });
+        if let Some(object) = self.selected() {
+            self.drag_offset = object.center - cursor;
+        }
+    }
+    fn selected(&mut self) -> Option<&mut Object> {
+        self.selected.map(|i| &mut self.objects[i])
+    }
+    pub fn drag(&mut self, cursor: Vec2<f32>) -> bool {
+        let center = cursor + self.drag_offset;
+        match self.selected() {
+            Some(object) if object.center != center => {
+                object.center = center;
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn scale_radius(&mut self, factor: f32) -> bool {
+        self.selected()
+            .map(|object| object.radius = (object.radius * factor).max(1.0))
+            .is_some()
+    }
+    /// Changes the IOR of the selection, returning the new IOR if there is one.
+    pub fn change_ior(&mut self, delta: f32) -> Option<f32> {
+        self.selected().map(|object| {
+            object.material.ior = (object.material.ior + delta).max(1.0);
+            object.material.ior
+        })
+    }
+    /// Switches the selection to the next of [`ROUGHNESSES`], returning it if there is one.
+    pub fn cycle_roughness(&mut self) -> Option<f32> {
+        self.selected().map(|object| {
+            let i = ROUGHNESSES
+                .iter()
+                .position(|&x| x == object.material.roughness)
+                .map_or(0, |i| (i + 1) % ROUGHNESSES.len());
+            object.material.roughness = ROUGHNESSES[i];
+            object.material.roughness
+        })
+    }
+    /// Switches to the next material of the [`library`], applying it to the selection if any.
+    pub fn cycle_material(&mut self) -> bool {
+        self.material_index = (self.material_index + 1) % library().len();
+        let material = library()[self.material_index].1;
+        self.selected()
+            .map(|object| object.material = material)
+            .is_some()
+    }
+    /// Name of the material given to new objects.
+    pub fn material_name(&self) -> &'static str {
+        library()[self.material_index].0
+    }
+    pub fn add(&mut self, center: Vec2<f32>) {
+        self.objects.push(Object {
+            center,
+            radius: 30.0,
+            material: library()[self.material_index].1,
+        });
+        self.selected = Some(self.objects.len() - 1);
+        self.drag_offset = Vec2::splat(0.0);
+    }
+    pub fn delete(&mut self) -> bool {
+        self.selected
+            .take()
+            .map(|i| {
+                self.objects.remove(i);
+            })
+            .is_some()
+    }
+}
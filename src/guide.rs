@@ -0,0 +1,237 @@
+//! Saving and loading the learned guide, so a run can start from the guide of an earlier one.
+//!
+//! The file is a header followed by the contents of both [`CascadeStorage`]s as little-endian
+//! `f32`s: `storage` is the guide itself and `next_storage` the samples it is learned from.
+//! The [`Learner`]'s progress isn't saved, so [`Schedule::Cumulative`] and
+//! [`Schedule::MovingAverage`] continue where they left off, but [`Schedule::Doubling`] starts
+//! over with passes that replace the loaded guide and [`Schedule::FrozenAfter`] warms up again.
+
+use super::*;
+use crate::scene::Draw;
+
+const MAGIC: [u8; 8] = *b"VLAMGDE1";
+/// Magic, checksum and layout.
+const HEADER_LEN: usize = MAGIC.len() + 8 + 4 * 6;
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across runs and platforms.
+struct Checksum(u64);
+impl Checksum {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+    fn write_u32(&mut self, x: u32) {
+        for byte in x.to_le_bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn write_f32(&mut self, x: f32) {
+        self.write_u32(x.to_bits());
+    }
+    fn write_vec3(&mut self, x: Vec3<f32>) {
+        self.write_f32(x.x);
+        self.write_f32(x.y);
+        self.write_f32(x.z);
+    }
+    fn write_material(&mut self, material: Material) {
+        self.write_vec3(material.emission);
+        self.write_vec3(material.absorption);
+        self.write_vec3(material.scattering);
+        self.write_vec3(material.albedo);
+        self.write_f32(material.ior);
+        self.write_f32(material.roughness);
+    }
+}
+
+/// Identifies the scene, the tracer and the layout of the guide, so a guide is only loaded where
+/// it was learned. `voxel_draws` are the draws rasterized for the voxel tracer, if it is used.
+pub fn checksum(objects: &[Object], voxel_draws: Option<&[Draw]>, storage: &CascadeStorage) -> u64 {
+    let mut hash = Checksum::new();
+    for word in layout(storage) {
+        hash.write_u32(word);
+    }
+    hash.write_u32(objects.len() as u32);
+    for object in objects {
+        hash.write_f32(object.center.x);
+        hash.write_f32(object.center.y);
+        hash.write_f32(object.radius);
+        hash.write_material(object.material);
+    }
+    match voxel_draws {
+        None => hash.write_u32(TracerKind::Analytic as u32),
+        Some(draws) => {
+            hash.write_u32(TracerKind::Voxel as u32);
+            hash.write_u32(draws.len() as u32);
+            for draw in draws {
+                match draw.brush {
+                    Brush::Rect(width, height) => {
+                        hash.write_u32(0);
+                        hash.write_f32(width);
+                        hash.write_f32(height);
+                    }
+                    Brush::Circle(radius) => {
+                        hash.write_u32(1);
+                        hash.write_f32(radius);
+                    }
+                }
+                hash.write_f32(draw.center.x);
+                hash.write_f32(draw.center.y);
+                hash.write_material(draw.material);
+            }
+        }
+    }
+    hash.0
+}
+
+fn layout(storage: &CascadeStorage) -> [u32; 6] {
+    [
+        storage.base_size.x,
+        storage.base_size.y,
+        storage.base_spacing.to_bits(),
+        storage.base_angles,
+        storage.angular_scale,
+        storage.num_cascades,
+    ]
+}
+
+fn describe(layout: &[u32]) -> String {
+    format!(
+        "{} cascades of {}x{} probes with {} base angles",
+        layout[5], layout[0], layout[1], layout[3]
+    )
+}
+
+pub fn save(
+    path: &Path,
+    storage: &CascadeStorage,
+    next_storage: &CascadeStorage,
+    checksum: u64,
+) -> std::io::Result<()> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(checksum.to_le_bytes());
+    for word in layout(storage) {
+        bytes.extend(word.to_le_bytes());
+    }
+    for storage in [storage, next_storage] {
+        for x in storage.data.copy_to_vec() {
+            bytes.extend(x.to_le_bytes());
+        }
+    }
+    std::fs::write(path, bytes)
+}
+
+/// Replaces the contents of `storage` and `next_storage` with the guide at `path`, if it was
+/// saved with the same `checksum`.
+pub fn load(
+    path: &Path,
+    storage: &CascadeStorage,
+    next_storage: &CascadeStorage,
+    checksum: u64,
+) -> Result<(), String> {
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    let words = |bytes: &[u8]| {
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>()
+    };
+    if bytes.len() < HEADER_LEN || bytes[..MAGIC.len()] != MAGIC {
+        return Err("not a guide file".to_string());
+    }
+    let saved_layout = words(&bytes[MAGIC.len() + 8..HEADER_LEN]);
+    if saved_layout != layout(storage) {
+        return Err(format!(
+            "it has {}, but the renderer uses {}",
+            describe(&saved_layout),
+            describe(&layout(storage))
+        ));
+    }
+    let saved_checksum =
+        u64::from_le_bytes(bytes[MAGIC.len()..MAGIC.len() + 8].try_into().unwrap());
+    if saved_checksum != checksum {
+        return Err("it was learned for a different scene".to_string());
+    }
+    let data = bytes[HEADER_LEN..]
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    let len = storage.data.len();
+    if data.len() != 2 * len {
+        return Err(format!(
+            "it holds {} values instead of {}",
+            data.len(),
+            2 * len
+        ));
+    }
+    storage.data.copy_from(&data[..len]);
+    next_storage.data.copy_from(&data[len..]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::pcgf_host;
+
+    #[test]
+    fn guide_round_trips_only_for_its_scene() {
+        let new = || CascadeStorage::new(Vec2::splat(16), 4, 3);
+        let [storage, next_storage] = [(); 2].map(|()| new());
+        for (i, storage) in [&storage, &next_storage].into_iter().enumerate() {
+            let data = (0..storage.data.len())
+                .map(|j| pcgf_host((i * storage.data.len() + j) as u32))
+                .collect::<Vec<_>>();
+            storage.data.copy_from(&data);
+        }
+        let objects = lenses(16.0);
+        // Unique to the process, so concurrent runs of the tests don't share the file.
+        let path = std::env::temp_dir().join(format!(
+            "vlam_guide_round_trip_{}.guide",
+            std::process::id()
+        ));
+        save(
+            &path,
+            &storage,
+            &next_storage,
+            checksum(&objects, None, &storage),
+        )
+        .unwrap();
+
+        let [loaded, loaded_next] = [(); 2].map(|()| new());
+        load(
+            &path,
+            &loaded,
+            &loaded_next,
+            checksum(&objects, None, &loaded),
+        )
+        .unwrap();
+        assert_eq!(loaded.data.copy_to_vec(), storage.data.copy_to_vec());
+        assert_eq!(
+            loaded_next.data.copy_to_vec(),
+            next_storage.data.copy_to_vec()
+        );
+
+        let mut moved = objects.clone();
+        moved[0].center.x += 1.0;
+        let error = load(
+            &path,
+            &loaded,
+            &loaded_next,
+            checksum(&moved, None, &loaded),
+        )
+        .unwrap_err();
+        assert!(error.contains("different scene"), "{error}");
+
+        let draws = Scene::simple().draws;
+        let voxel = checksum(&objects, Some(&draws), &loaded);
+        let error = load(&path, &loaded, &loaded_next, voxel).unwrap_err();
+        assert!(error.contains("different scene"), "{error}");
+        let mut moved = draws.clone();
+        moved[0].center.y += 1.0;
+        assert_ne!(checksum(&objects, Some(&moved), &loaded), voxel);
+
+        let [other, other_next] = [(); 2].map(|()| CascadeStorage::new(Vec2::splat(16), 4, 2));
+        let error = load(&path, &other, &other_next, checksum(&objects, None, &other)).unwrap_err();
+        assert!(error.contains("2 cascades"), "{error}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
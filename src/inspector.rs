@@ -0,0 +1,201 @@
+//! Inspecting the guide at a single position: the weights of every direction in each cascade and
+//! the distribution the guide samples them from.
+
+use std::io::Write;
+
+use super::*;
+
+/// Where the guide is inspected and which of its cascades are shown.
+pub struct Inspector {
+    /// Inspected position in render coordinates, if any.
+    pub pos: Option<Vec2<f32>>,
+    /// Bit `i` is set if cascade `i` is shown.
+    pub cascades: u32,
+}
+impl Default for Inspector {
+    fn default() -> Self {
+        Self {
+            pos: None,
+            cascades: u32::MAX,
+        }
+    }
+}
+impl Inspector {
+    /// Inspects `cursor`, or stops inspecting if already doing so.
+    pub fn toggle(&mut self, cursor: Vec2<f32>) {
+        self.pos = match self.pos {
+            Some(_) => None,
+            None => Some(cursor),
+        };
+    }
+    pub fn toggle_cascade(&mut self, cascade: u32) {
+        self.cascades ^= 1 << cascade;
+    }
+    pub fn shows(&self, cascade: u32) -> bool {
+        (self.cascades >> cascade) & 1 == 1
+    }
+}
+
+/// The guide at one position, read back to the host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeWeights {
+    /// Bilinearly interpolated weight of each direction, per cascade.
+    pub weights: Vec<Vec<f32>>,
+    /// Density with which the cascades up to and including each one choose each of its
+    /// directions, relative to uniform sampling; as sampled by `CascadeStorage::sample_direction`.
+    pub pdfs: Vec<Vec<f32>>,
+}
+impl ProbeWeights {
+    /// Reads the weights at `pos`, in render coordinates.
+    pub fn read(storage: &CascadeStorage, pos: Vec2<f32>) -> Self {
+        let weights = (0..storage.num_cascades)
+            .map(|cascade| {
+                let angles = storage.base_angles << (cascade * storage.angular_scale);
+                let mut weights = vec![0.0; angles as usize];
+                for (start, w) in probe_corners(storage, cascade, pos) {
+                    let probe = storage
+                        .data
+                        .view(start as usize..(start + angles) as usize)
+                        .copy_to_vec();
+                    for (weight, x) in weights.iter_mut().zip(probe) {
+                        *weight += x * w;
+                    }
+                }
+                weights
+            })
+            .collect::<Vec<_>>();
+        let pdfs = pdfs(storage, &weights);
+        Self { weights, pdfs }
+    }
+    /// Prints each shown cascade, listing its weights if there are few enough to read.
+    pub fn print(&self, inspector: &Inspector) {
+        for (cascade, (weights, pdfs)) in self.weights.iter().zip(&self.pdfs).enumerate() {
+            if !inspector.shows(cascade as u32) {
+                continue;
+            }
+            let total = weights.iter().sum::<f32>();
+            let (peak, max_pdf) = pdfs
+                .iter()
+                .copied()
+                .enumerate()
+                .fold((0, 0.0), |a, b| if b.1 > a.1 { b } else { a });
+            println!(
+                "Cascade {cascade}: {} directions, total weight {total:.4}, peak pdf {max_pdf:.3} \
+                 at {:.1}°",
+                weights.len(),
+                (peak as f32 + 0.5) / weights.len() as f32 * 360.0
+            );
+            if weights.len() <= 16 {
+                let weights = weights
+                    .iter()
+                    .map(|w| format!("{w:.4}"))
+                    .collect::<Vec<_>>();
+                println!("  weights: {}", weights.join(", "));
+            }
+        }
+    }
+    /// Writes one row per direction of every cascade.
+    pub fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "cascade,direction,angle,weight,pdf")?;
+        for (cascade, (weights, pdfs)) in self.weights.iter().zip(&self.pdfs).enumerate() {
+            for (direction, (weight, pdf)) in weights.iter().zip(pdfs).enumerate() {
+                let angle = (direction as f32 + 0.5) / weights.len() as f32 * TAU;
+                writeln!(out, "{cascade},{direction},{angle},{weight},{pdf}")?;
+            }
+        }
+        Ok(())
+    }
+    /// Each cascade's pdf divided by its maximum, concatenated, as plotted by the inspector.
+    pub fn plot(&self) -> Vec<f32> {
+        self.pdfs
+            .iter()
+            .flat_map(|pdfs| {
+                let max = pdfs.iter().copied().fold(0.0, f32::max);
+                pdfs.iter()
+                    .map(move |&pdf| if max > 0.0 { pdf / max } else { 0.0 })
+            })
+            .collect()
+    }
+}
+
+/// Index of the first direction of each probe that `CascadeStorage::get_bilinear` interpolates
+/// at `pos`, with its weight.
+fn probe_corners(storage: &CascadeStorage, cascade: u32, pos: Vec2<f32>) -> [(u32, f32); 4] {
+    let size = storage.base_size.map(|x| x >> cascade);
+    let spacing = storage.base_spacing * (1 << cascade) as f32;
+    let pos = Vec2::new(
+        (pos.x / spacing - 0.5).clamp(0.0, (size.x - 1) as f32),
+        (pos.y / spacing - 0.5).clamp(0.0, (size.y - 1) as f32),
+    );
+    let angles = storage.base_angles << (cascade * storage.angular_scale);
+    let start = |x: f32, y: f32| {
+        let [x, y] = [(x as u32).min(size.x - 1), (y as u32).min(size.y - 1)];
+        cascade * storage.cascade_size() + x * size.y * angles + y * angles
+    };
+    let [x, y] = [pos.x.floor(), pos.y.floor()];
+    let [fx, fy] = [pos.x - x, pos.y - y];
+    [
+        (start(x, y), (1.0 - fx) * (1.0 - fy)),
+        (start(x + 1.0, y), fx * (1.0 - fy)),
+        (start(x, y + 1.0), (1.0 - fx) * fy),
+        (start(x + 1.0, y + 1.0), fx * fy),
+    ]
+}
+
+/// The density of each direction relative to uniform, given the `weights` of each cascade: every
+/// cascade after the first splits the probability of its parent direction among its children in
+/// proportion to their weights.
+fn pdfs(storage: &CascadeStorage, weights: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let mut pdfs: Vec<Vec<f32>> = vec![];
+    for (cascade, weights) in weights.iter().enumerate() {
+        let branches = if cascade == 0 {
+            storage.base_angles as usize
+        } else {
+            1 << storage.angular_scale
+        };
+        let pdf = weights
+            .chunks(branches)
+            .enumerate()
+            .flat_map(|(parent, siblings)| {
+                let parent_pdf = pdfs.last().map_or(1.0, |pdfs| pdfs[parent]);
+                let total = siblings.iter().sum::<f32>();
+                siblings
+                    .iter()
+                    .map(move |&weight| parent_pdf * branches as f32 * weight / total)
+            })
+            .collect();
+        pdfs.push(pdf);
+    }
+    pdfs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::eval;
+    use crate::utils::pcgf_host;
+
+    #[test]
+    fn host_weights_match_device() {
+        let storage = CascadeStorage::new(Vec2::splat(16), 4, 3);
+        let data = (0..storage.data.len() as u32)
+            .map(|i| 0.1 + pcgf_host(i))
+            .collect::<Vec<_>>();
+        storage.data.copy_from(&data);
+        let pos = Vec2::new(5.3, 11.8);
+        let probe = ProbeWeights::read(&storage, pos);
+
+        for (cascade, (weights, pdfs)) in probe.weights.iter().zip(&probe.pdfs).enumerate() {
+            let cascade = cascade as u32;
+            let device = eval(weights.len() as u32, |angle| {
+                storage.get_bilinear(cascade.expr(), pos.expr(), angle)
+            });
+            for (host, device) in weights.iter().zip(device) {
+                assert!((host - device).abs() < 1e-5, "{host} != {device}");
+            }
+            // The mean density over all directions is that of uniform sampling.
+            let mean = pdfs.iter().sum::<f32>() / pdfs.len() as f32;
+            assert!((mean - 1.0).abs() < 1e-4, "cascade {cascade}: {mean}");
+        }
+    }
+}
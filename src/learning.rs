@@ -0,0 +1,107 @@
+/// How the guide in `storage` is updated from the samples accumulated in `next_storage`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    /// Sum every sample ever taken, never clearing `next_storage`.
+    Cumulative,
+    /// Exponential moving average of per-iteration estimates, with the given weight of the newest.
+    MovingAverage { rate: f32 },
+    /// Training passes of doubling length, each replacing the guide with its own estimate
+    /// (Müller et al. 2017, "Practical Path Guiding").
+    Doubling,
+    /// [`Schedule::Cumulative`] for the given number of iterations, then frozen.
+    FrozenAfter { warmup: u32 },
+}
+impl Schedule {
+    pub const PRESETS: [Schedule; 4] = [
+        Schedule::Cumulative,
+        Schedule::MovingAverage { rate: 0.05 },
+        Schedule::Doubling,
+        Schedule::FrozenAfter { warmup: 256 },
+    ];
+}
+
+/// One application of `storage = keep * storage + scale * next_storage + prior`,
+/// optionally followed by clearing `next_storage`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Update {
+    pub keep: f32,
+    pub scale: f32,
+    pub prior: f32,
+    pub clear: bool,
+}
+
+pub struct Learner {
+    pub schedule: Schedule,
+    /// Weight given to every direction regardless of the samples, so no direction has zero probability.
+    pub prior: f32,
+    /// Iterations that only train the guide, before any are added to the display.
+    pub training_iterations: u32,
+    iteration: u32,
+    pass_start: u32,
+    pass_length: u32,
+}
+impl Learner {
+    pub fn new(schedule: Schedule) -> Self {
+        Self {
+            schedule,
+            prior: 0.01,
+            training_iterations: 0,
+            iteration: 0,
+            pass_start: 0,
+            pass_length: 1,
+        }
+    }
+    pub fn reset(&mut self) {
+        self.iteration = 0;
+        self.pass_start = 0;
+        self.pass_length = 1;
+    }
+    /// Iterations traced since the last reset.
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+    pub fn training(&self) -> bool {
+        self.iteration < self.training_iterations
+    }
+    /// Value `next_storage` should hold after a reset.
+    pub fn initial_next(&self) -> f32 {
+        match self.schedule {
+            Schedule::Cumulative | Schedule::FrozenAfter { .. } => 1.0,
+            Schedule::MovingAverage { .. } | Schedule::Doubling => 0.0,
+        }
+    }
+    /// Advances by one traced iteration, returning how to update the guide, if at all.
+    pub fn step(&mut self) -> Option<Update> {
+        self.iteration += 1;
+        let cumulative = Update {
+            keep: 0.0,
+            scale: 0.5,
+            prior: self.prior,
+            clear: false,
+        };
+        match self.schedule {
+            Schedule::Cumulative => Some(cumulative),
+            Schedule::FrozenAfter { warmup } => (self.iteration <= warmup).then_some(cumulative),
+            Schedule::MovingAverage { rate } => Some(Update {
+                keep: 1.0 - rate,
+                scale: rate,
+                prior: rate * self.prior,
+                clear: true,
+            }),
+            Schedule::Doubling => {
+                if self.iteration - self.pass_start < self.pass_length {
+                    return None;
+                }
+                let length = self.pass_length;
+                self.pass_start = self.iteration;
+                self.pass_length *= 2;
+                Some(Update {
+                    keep: 0.0,
+                    scale: 1.0 / length as f32,
+                    prior: self.prior,
+                    clear: true,
+                })
+            }
+        }
+    }
+}
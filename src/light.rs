@@ -0,0 +1,186 @@
+use std::f32::consts::PI;
+
+use super::*;
+use crate::utils::pcg3d;
+
+/// How the light tracing estimate is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightTracing {
+    Off,
+    Only,
+    /// Equal-weight average with the pixel estimator; both are unbiased, so the average is too.
+    Combined,
+}
+impl LightTracing {
+    pub fn next(self) -> Self {
+        match self {
+            LightTracing::Off => LightTracing::Only,
+            LightTracing::Only => LightTracing::Combined,
+            LightTracing::Combined => LightTracing::Off,
+        }
+    }
+    /// Weight of the light tracing estimate in the display.
+    pub fn weight(self) -> f32 {
+        match self {
+            LightTracing::Off => 0.0,
+            LightTracing::Only => 1.0,
+            LightTracing::Combined => 0.5,
+        }
+    }
+}
+
+pub(crate) const STEP: f32 = 1.0;
+pub(crate) const MAX_STEPS: u32 = 4096;
+pub(crate) const WEIGHT_CUTOFF: f32 = 1e-4;
+
+/// Distribution of emitted power over the objects of an [`AnalyticTracer`], for starting paths
+/// at the lights.
+///
+/// Emitters are treated as volumes emitting `opacity * emission` per unit length, so nearly
+/// opaque emitters are handled poorly.
+pub struct Emitters {
+    // Cumulative distribution over objects by emitted power.
+    cdf: Buffer<f32>,
+    total_power: Buffer<f32>,
+}
+impl Emitters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cdf: DEVICE.create_buffer_from_fn(capacity, |_| 0.0),
+            total_power: DEVICE.create_buffer_from_fn(1, |_| 0.0),
+        }
+    }
+    fn power(object: &Object) -> f32 {
+        let Color { emission, opacity } = object.material.color();
+        let area = PI * object.radius * object.radius;
+        (0.2126 * emission.x * opacity.x
+            + 0.7152 * emission.y * opacity.y
+            + 0.0722 * emission.z * opacity.z)
+            * area
+            * TAU
+    }
+    /// Rebuilds the distribution; must be called whenever the objects change.
+    pub fn update(&self, objects: &[Object]) {
+        let mut cdf = vec![f32::INFINITY; self.cdf.len()];
+        let mut total = 0.0;
+        for (i, object) in objects.iter().enumerate() {
+            total += Self::power(object);
+            cdf[i] = total;
+        }
+        self.cdf.copy_from(&cdf);
+        self.total_power.copy_from(&[total]);
+    }
+    pub fn total_power(&self) -> Expr<f32> {
+        self.total_power.read(0_u32.expr())
+    }
+    /// Samples a starting position and direction, returning them with the emitted power
+    /// divided by their pdf and by the 2π from averaging radiance over angles.
+    /// Only valid if [`Self::total_power`] is positive.
+    #[tracked]
+    pub fn sample(
+        &self,
+        world: &AnalyticTracer,
+        rand: Expr<Vec3<f32>>,
+        rand2: Expr<Vec3<f32>>,
+    ) -> (Expr<Vec2<f32>>, Expr<Vec2<f32>>, Expr<Radiance>) {
+        let total = self.total_power();
+        let target = rand.x * total;
+        let emitter = 0_u32.var();
+        loop {
+            if self.cdf.read(emitter) >= target || emitter + 1 >= world.capacity() as u32 {
+                break;
+            }
+            *emitter += 1;
+        }
+        let object = world.objects.read(emitter);
+        let prev = if emitter == 0 {
+            0.0_f32.expr()
+        } else {
+            self.cdf.read(emitter - 1)
+        };
+        let pdf = (self.cdf.read(emitter) - prev) / total;
+
+        let pos = object.center + (rand.y * TAU).direction() * object.radius * rand.z.sqrt();
+        let dir = (rand2.x * TAU).direction();
+        let area = PI * object.radius.sqr();
+        let color = object.material.color();
+        let weight = color.emission * color.opacity * area / pdf;
+        (pos, dir, weight)
+    }
+}
+
+/// Traces paths from the lights, splatting their track length into a per-pixel estimate of the
+/// same angle-averaged radiance the display shows.
+pub struct LightTracer {
+    accum: Buffer<f32>,
+    size: Vec2<u32>,
+    pub paths: u32,
+    pub passes: u32,
+}
+impl LightTracer {
+    pub fn new(size: Vec2<u32>, paths: u32) -> Self {
+        Self {
+            accum: DEVICE.create_buffer_from_fn((size.x * size.y * 3) as usize, |_| 0.0),
+            size,
+            paths,
+            passes: 0,
+        }
+    }
+    pub fn clear(&mut self) {
+        self.accum.copy_from(&vec![0.0; self.accum.len()]);
+        self.passes = 0;
+    }
+    #[tracked]
+    fn splat(&self, pos: Expr<Vec2<f32>>, value: Expr<Radiance>) {
+        if (pos >= 0.0).all() && (pos < self.size.expr().cast_f32()).all() {
+            let pixel = pos.cast_u32();
+            let index = (pixel.x + pixel.y * self.size.x) * 3;
+            self.accum.atomic_fetch_add(index, value.x);
+            self.accum.atomic_fetch_add(index + 1, value.y);
+            self.accum.atomic_fetch_add(index + 2, value.z);
+        }
+    }
+    /// Traces one path; dispatched over `paths` threads per pass.
+    #[tracked]
+    pub fn trace(&self, world: &AnalyticTracer, emitters: &Emitters, seed: Expr<u32>) {
+        let path = dispatch_id().x;
+        let rand = pcg3df(Vec3::expr(path, seed, 0x5eed));
+        let rand2 = pcg3df(Vec3::expr(path, seed, 0x5eee));
+
+        if emitters.total_power() > 0.0 {
+            let (pos, dir, weight) = emitters.sample(world, rand, rand2);
+            let pos = pos.var();
+            let dir = dir.var();
+            let weight = (weight / self.paths as f32).var();
+
+            for step in 0_u32.expr()..MAX_STEPS.expr() {
+                let bounce_seed = pcg3d(Vec3::expr(path, seed, step)).x;
+                let traced = world.trace(**pos, **dir, STEP.expr(), bounce_seed);
+                let transmittance = traced.fluence.transmittance;
+                self.splat(
+                    (pos + traced.final_pos) * 0.5,
+                    weight * STEP * (1.0 + transmittance) * 0.5,
+                );
+                *weight *= transmittance;
+                *pos = traced.final_pos;
+                *dir = traced.final_dir;
+                if weight.reduce_max() < WEIGHT_CUTOFF
+                    || (pos < 0.0).any()
+                    || (pos >= self.size.expr().cast_f32()).any()
+                {
+                    break;
+                }
+            }
+        }
+    }
+    /// Estimate at `pixel` after `passes` passes.
+    #[tracked]
+    pub fn mean(&self, pixel: Expr<Vec2<u32>>, passes: Expr<u32>) -> Expr<Radiance> {
+        let index = (pixel.x + pixel.y * self.size.x) * 3;
+        Vec3::expr(
+            self.accum.read(index),
+            self.accum.read(index + 1),
+            self.accum.read(index + 2),
+        ) / keter::max(passes, 1).cast_f32()
+    }
+}
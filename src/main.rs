@@ -1,26 +1,70 @@
-#![feature(more_float_constants)]
-
-use std::f32::consts::{PHI, TAU};
+use std::{
+    f32::consts::TAU,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use analytic::{AnalyticTracer, Object};
+use adaptive::AdaptiveSampler;
+use analytic::{AnalyticTracer, Object, TracedRay, lenses};
+use aov::{Aov, Aovs};
+use cli::{CliError, Config, TracerKind, USAGE};
+use convergence::{Convergence, StoppingRule};
+use denoise::{Denoiser, PASSES};
+use display::{DisplaySettings, Histogram, false_color, tonemap, write_pfm};
+use editor::ObjectEditor;
+use inspector::{Inspector, ProbeWeights};
 use keter::{
     lang::types::vector::{Vec2, Vec3, Vec4},
     prelude::*,
 };
 use keter_testbed::{App, KeyCode, MouseButton};
+use learning::{Learner, Schedule};
+use light::{Emitters, LightTracer, LightTracing};
+use material::{Material, MaterialId, MaterialTable};
+use photons::PhotonMap;
+use renderer::{Presenter, Renderer};
 use scene::{Brush, Scene};
-use utils::{luma, pcg3df};
+use sequence::{SampleStream, Sequence};
+use stats::Stats;
+use utils::{luma, pcg, pcg3d, pcg3df};
 use voxel::VoxelTracer;
 
+mod adaptive;
 mod analytic;
+mod aov;
+mod bench;
+mod cli;
+mod convergence;
+mod denoise;
+mod display;
+mod editor;
+mod guide;
+mod inspector;
+mod learning;
+mod light;
+mod material;
+mod photons;
+mod procedural;
+mod reference;
+mod renderer;
 mod scene;
+mod sequence;
+mod stats;
 mod utils;
 mod voxel;
 
+/// The device the tests run on: the CPU backend, so they check the kernels without a GPU. It
+/// shadows the prelude's `DEVICE`, so every module's buffers and kernels share it.
+#[cfg(test)]
+static DEVICE: std::sync::LazyLock<keter::runtime::Device> = std::sync::LazyLock::new(|| {
+    keter::Context::new(std::env::current_exe().unwrap()).create_device("cpu")
+});
+
 pub type Emission = Vec3<f32>;
 pub type Opacity = Vec3<f32>;
 pub type Radiance = Vec3<f32>;
 pub type Transmittance = Vec3<f32>;
+pub type Albedo = Vec3<f32>;
 
 #[derive(Clone, Copy, Debug, PartialEq, Value)]
 #[repr(C)]
@@ -56,6 +100,7 @@ impl FluenceExpr {
     }
 }
 
+/// Emission and extinction of a volume, the part of a [`Material`] that [`Fluence`] depends on.
 #[derive(Clone, Copy, Debug, PartialEq, Value)]
 #[repr(C)]
 pub struct Color {
@@ -90,6 +135,20 @@ impl ColorExpr {
     }
 }
 
+/// A world that paths can be traced through, scattering along the way.
+pub trait PathTracer {
+    /// Traces `len` along the ray, with `seed` making the random choices at each interaction.
+    fn trace_path(
+        &self,
+        pos: Expr<Vec2<f32>>,
+        dir: Expr<Vec2<f32>>,
+        len: Expr<f32>,
+        seed: Expr<u32>,
+    ) -> Expr<TracedRay>;
+    /// The material of the volume containing `pos`.
+    fn material_at(&self, pos: Expr<Vec2<f32>>) -> Expr<Material>;
+}
+
 #[tracked]
 fn bilinear(pos: Expr<Vec2<f32>>) -> [(Expr<Vec2<u32>>, Expr<f32>); 4] {
     let f = pos.fract();
@@ -112,6 +171,26 @@ struct CascadeStorage {
     num_cascades: u32,
 }
 impl CascadeStorage {
+    /// Storage with probes at every pixel of `base_size`, all weights starting at 1.
+    fn new(base_size: Vec2<u32>, base_angles: u32, num_cascades: u32) -> Self {
+        Self {
+            data: DEVICE.create_buffer_from_fn(
+                base_size.x as usize
+                    * base_size.y as usize
+                    * base_angles as usize
+                    * num_cascades as usize,
+                |_| 1.0,
+            ),
+            base_size,
+            base_spacing: 1.0,
+            base_angles,
+            angular_scale: 2,
+            num_cascades,
+        }
+    }
+    fn memory_bytes(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f32>()
+    }
     fn cascade_size(&self) -> u32 {
         self.base_size.x * self.base_size.y * self.base_angles
     }
@@ -167,6 +246,93 @@ impl CascadeStorage {
             self.add(cascade, pos, angle, value)
         })
     }
+    /// Length of the segment traced for cascade `i`, out to the start of the next cascade.
+    fn segment_length(&self, i: u32) -> f32 {
+        2.0 * if i == 0 {
+            1.0
+        } else {
+            (3 << ((i - 1) * self.angular_scale)) as f32
+        }
+    }
+    /// Draws a direction from a mixture of the guide and uniform sampling, with `uniform_fraction`
+    /// the probability of the latter. Returns the direction's index in the last cascade, the ratio
+    /// of its mixture pdf to the uniform pdf, and the direction.
+    #[tracked]
+    fn sample_direction(
+        &self,
+        stream: &SampleStream,
+        pos: Expr<Vec2<f32>>,
+        uniform_fraction: Expr<f32>,
+    ) -> (Expr<u32>, Expr<f32>, Expr<Vec2<f32>>) {
+        let uniform = stream.get(self.num_cascades + 1) < uniform_fraction;
+        let index = 0_u32.var();
+        // Ratio of the guided pdf to the uniform pdf of the chosen direction.
+        let guided_pdf = 1.0_f32.var();
+        for i in (0..self.num_cascades) {
+            let rand = stream.get(i);
+            // The first cascade picks one of its base directions, the rest subdivide the
+            // direction picked by the previous one.
+            let branches = if i == 0 {
+                self.base_angles
+            } else {
+                1 << self.angular_scale
+            };
+            let i = i.expr();
+            *index *= branches;
+            let weights = (0..branches)
+                .map(|j| self.get_bilinear(i, pos, index + j))
+                .collect::<Vec<_>>();
+            let total = weights.iter().fold(0.0_f32.expr(), |a, &b| a + b);
+            let j = if uniform {
+                keter::min((rand * branches as f32).cast_u32(), branches - 1)
+            } else {
+                // The number of branches whose cumulative weight is at most `rand`.
+                let j = 0_u32.var();
+                let cumulative = 0.0_f32.var();
+                for &weight in &weights[..weights.len() - 1] {
+                    *cumulative += weight / total;
+                    if rand >= cumulative {
+                        *j += 1;
+                    }
+                }
+                **j
+            };
+            let chosen = 0.0_f32.var();
+            for (k, &weight) in weights.iter().enumerate() {
+                if j == k as u32 {
+                    *chosen = weight;
+                }
+            }
+            *index += j;
+            *guided_pdf *= branches as f32 * chosen / total;
+        }
+        let bias = uniform_fraction + (1.0 - uniform_fraction) * guided_pdf;
+        let max_index = self.base_angles << (self.angular_scale * (self.num_cascades - 1));
+        let angle =
+            (index.cast_f32() + stream.get(self.num_cascades)) / (max_index as f32).expr() * TAU;
+        (**index, bias, angle.direction())
+    }
+    /// Traces the path from `pos` one segment per cascade.
+    #[tracked]
+    fn trace_segments(
+        &self,
+        world: &dyn PathTracer,
+        pos: Expr<Vec2<f32>>,
+        dir: Expr<Vec2<f32>>,
+        path_seed: Expr<u32>,
+    ) -> Vec<Expr<TracedRay>> {
+        let mut segments = vec![];
+        let mut pos = pos;
+        let mut dir = dir;
+        for i in (0..self.num_cascades) {
+            let traced =
+                world.trace_path(pos, dir, self.segment_length(i).expr(), pcg(path_seed + i));
+            pos = traced.final_pos;
+            dir = traced.final_dir;
+            segments.push(traced);
+        }
+        segments
+    }
 }
 
 fn cascade_colors() -> [Vec3<f32>; 6] {
@@ -184,164 +350,99 @@ fn cascade_colors() -> [Vec3<f32>; 6] {
 }
 
 const DISPLAY_SIZE: u32 = 1024;
+/// Limits of the render resolution, which is always a power of two.
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 4096;
 const MAX_ITERS: u32 = 1000;
+const NUM_CASCADES: u32 = 6;
+/// Limit on the weights of a guide, which are indexed with `u32` and stored twice.
+const MAX_GUIDE_ENTRIES: u64 = 1 << 30;
+/// Fractions of uniformly sampled directions cycled through at runtime.
+const UNIFORM_FRACTIONS: [f32; 4] = [0.0, 0.1, 0.25, 0.5];
+/// Guide training iteration counts cycled through at runtime.
+const TRAINING_ITERATIONS: [u32; 4] = [0, 32, 128, 512];
+/// Sample index of the first training iteration, far from those used for rendering.
+const TRAINING_INDEX_OFFSET: u32 = 1 << 24;
+/// Iterations between estimates of the remaining error.
+const ERROR_INTERVAL: u32 = 16;
+/// Iterations between readbacks of the inspected weights while rendering.
+const PROBE_INTERVAL: u32 = 16;
+
+/// Wall time the device takes for the work queued by `render`, waiting for earlier work first so
+/// it isn't counted.
+fn time_on_device(render: impl FnOnce()) -> Duration {
+    let stream = DEVICE.default_stream();
+    stream.synchronize();
+    let start = Instant::now();
+    render();
+    stream.synchronize();
+    start.elapsed()
+}
 
-fn main() {
-    let app = App::new("Vlam", [DISPLAY_SIZE; 2])
-        .scale(2048 / DISPLAY_SIZE)
-        .agx()
-        .init();
-
-    let world = AnalyticTracer::new(&[
-        Object {
-            center: Vec2::new(3.0 * DISPLAY_SIZE as f32 / 4.0, DISPLAY_SIZE as f32 / 2.0),
-            radius: 5.0,
-            refraction_index: 1.0,
-            color: Color::new(Vec3::splat(20.0), Vec3::splat(2.0)),
-        },
-        Object {
-            center: Vec2::new(DISPLAY_SIZE as f32 / 2.0, DISPLAY_SIZE as f32 / 2.0),
-            radius: 100.0,
-            refraction_index: 1.5,
-            color: Color::new(Vec3::splat(0.0), Vec3::splat(0.0)),
-        },
-        Object {
-            center: Vec2::new(DISPLAY_SIZE as f32 / 4.0, DISPLAY_SIZE as f32 / 2.0),
-            radius: 50.0,
-            refraction_index: 1.5,
-            color: Color::new(Vec3::splat(0.0), Vec3::splat(0.0)),
-        },
-    ]);
-    // VoxelTracer::new(Vec2::splat(DISPLAY_SIZE));
-
-    let [storage, next_storage] = [(); 2].map(|()| CascadeStorage {
-        data: DEVICE.create_buffer_from_fn((DISPLAY_SIZE * DISPLAY_SIZE * 4 * 6) as usize, |_| 1.0),
-        base_size: Vec2::splat(DISPLAY_SIZE),
-        base_spacing: 1.0,
-        base_angles: 4,
-        angular_scale: 2,
-        num_cascades: 6,
-    });
+fn print_done(iterations: u32, error: Option<f32>) {
+    match error {
+        Some(error) => println!("Done after {iterations} iterations, relative error {error:.4}"),
+        None => println!("Done after {iterations} iterations"),
+    }
+}
 
-    let display =
-        DEVICE.create_tex2d::<Vec3<f32>>(PixelStorage::Float4, DISPLAY_SIZE, DISPLAY_SIZE, 1);
-
-    // let compute_diff = DEVICE.create_kernel::<fn()>(&track!(|| {
-    //     world.compute_diff();
-    // }));
-    let copy_storage = DEVICE.create_kernel::<fn()>(&track!(|| {
-        let index = dispatch_id().x;
-        storage
-            .data
-            .write(index, next_storage.data.read(index) * 0.5 + 0.01);
-    }));
-    let draw_kernel = DEVICE.create_kernel::<fn(u32)>(&track!(|iterations| {
-        let pixel = dispatch_id().xy();
-        app.display()
-            .write(pixel, display.read(pixel) / iterations.cast_f32());
-    }));
-    let trace_simple_kernel = DEVICE.create_kernel::<fn(u32)>(&track!(|t| {
-        let pixel = dispatch_id().xy();
-        let pos = pixel.cast_f32() + 0.5;
-
-        let angle = (pcg3df(pixel.extend(35)).x + (t.cast_f32() * PHI) % 1.0) * TAU;
-        let dir = angle.direction();
-        let radiance = world.trace(pos, dir, 9999.0.expr()).fluence.radiance;
-        display.write(pixel, display.read(pixel) + radiance);
-    }));
-    let trace_kernel = DEVICE.create_kernel::<fn(u32)>(&track!(|t| {
-        let pixel = dispatch_id().xy();
-        let pos = pixel.cast_f32() + 0.5;
-        let index = 0_u32.var();
-        let bias = 1.0_f32.var();
-        for i in (0..storage.num_cascades) {
-            let i = i.expr();
-            *index *= 4; // TODO: Fixed with angular_scale = 2, base_angles = 4.
-            let weights = Vec4::expr(
-                storage.get_bilinear(i, pos, index + 0),
-                storage.get_bilinear(i, pos, index + 1),
-                storage.get_bilinear(i, pos, index + 2),
-                storage.get_bilinear(i, pos, index + 3),
-            );
-            let weights = weights / weights.reduce_sum();
-            let rand = pcg3df(pixel.extend(t * (storage.num_cascades + 1) + i)).x;
-            let j = if rand < weights.x {
-                0.expr()
-            } else if rand < weights.x + weights.y {
-                1.expr()
-            } else if rand < weights.x + weights.y + weights.z {
-                2.expr()
-            } else {
-                3.expr()
-            };
-            *index += j;
-            *bias *= 4.0 * Expr::<[f32; 4]>::from(weights).read(j);
+/// Writes the output and its AOVs, if requested, returning whether all were saved.
+fn save_outputs(renderer: &Renderer, config: &Config, stats: &mut Stats) -> bool {
+    let mut saved = true;
+    let aovs = config.aovs.iter().map(|&aov| (config.aov_path(aov), aov));
+    for (path, aov) in [(config.output.clone(), Aov::Radiance)]
+        .into_iter()
+        .chain(aovs)
+    {
+        let Some(path) = path else {
+            continue;
+        };
+        match renderer.save(&path, aov, stats) {
+            Ok(()) => println!("Saved {}", path.display()),
+            Err(err) => {
+                eprintln!("Failed to save {}: {err}", path.display());
+                saved = false;
+            }
         }
-        let max_index = 1 << (storage.angular_scale * storage.num_cascades);
-        let angle = (index.cast_f32()
-            + pcg3df(pixel.extend(t * (storage.num_cascades + 1) + storage.num_cascades)).x)
-            / (max_index as f32).expr()
-            * TAU;
-        let dir = angle.direction();
-
-        let orig_pos = pos;
-        let mut fluences = vec![];
-        let mut pos = pos;
-        let mut dir = dir;
+    }
+    saved
+}
 
-        for i in (0..storage.num_cascades) {
-            let traced = world.trace(
-                pos,
-                dir,
-                (2.0 * if i == 0 {
-                    1.0
-                } else {
-                    (3 << ((i - 1) * storage.angular_scale)) as f32
-                })
-                .expr(),
-            );
-            pos = traced.final_pos;
-            dir = traced.final_dir;
-            fluences.push(traced.fluence);
+fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("bench-occupancy") => return bench::occupancy(),
+        Some("bench-sampling") => return bench::sampling(),
+        Some("bench-scenes") => return bench::scenes(),
+        Some("bench-throughput") => {
+            let path = std::env::args().nth(2);
+            return bench::throughput(path.as_deref().unwrap_or("throughput.csv"));
         }
-
-        let mut radiance = Radiance::splat(0.0).expr();
-
-        for i in (0..storage.num_cascades).rev() {
-            radiance = fluences[i as usize].over_radiance(radiance);
-            let index = index >> (storage.angular_scale * (storage.num_cascades - 1 - i));
-            next_storage.add_bilinear(i.expr(), orig_pos, index, luma(radiance) / bias);
+        _ => {}
+    }
+    let config = match Config::parse(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(CliError::Help) => return println!("{USAGE}"),
+        Err(CliError::Invalid(message)) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            std::process::exit(2);
         }
-
-        display.write(pixel, display.read(pixel) + radiance / bias);
-    }));
-
-    let draw_rc_overlay =
-        DEVICE.create_kernel::<fn(Vec2<f32>, f32)>(&track!(|cursor, exposure| {
-            let pixel = dispatch_id().xy();
-            let pos = pixel.cast_f32() + 0.5;
-            let delta = pos - cursor;
-            let dist = delta.length();
-            let angle = delta.angle();
-            let cascade = keter::max((dist / 2.0).log2() / 2.0, 0.0).ceil().cast_u32();
-            if cascade >= storage.num_cascades {
-                return;
-            }
-            let index =
-                (angle / TAU).rem_euclid(1.0) * (4 << (storage.angular_scale * cascade)).cast_f32();
-            let index = index.cast_u32();
-            let weight = storage.get_bilinear(cascade, cursor, index);
-            let color = weight / exposure * cascade_colors().expr().read(cascade);
-            app.display().write(pixel, color);
-        }));
-    let draw_probe_overlay = DEVICE.create_kernel::<fn(u32)>(&track!(|cascade| {
-        let pos = dispatch_id().xy().cast_f32() + 0.5;
-        let pos = pos * storage.base_spacing * (1 << cascade).cast_f32();
-        app.set_pixel(
-            pos.floor().cast_i32(),
-            cascade_colors().expr().read(cascade),
-        );
-    }));
+    };
+    let scene = config.scene().unwrap_or_else(|message| {
+        eprintln!("error: {message}");
+        std::process::exit(2);
+    });
+    if let Err(message) = config.check_outputs() {
+        eprintln!("error: {message}");
+        std::process::exit(1);
+    }
+    let mut editor = ObjectEditor::new(scene.objects());
+    let mut renderer = Renderer::new(&config, &scene, &editor.objects, config.size);
+    if let Some(path) = config.guide.as_deref().filter(|path| path.exists()) {
+        match renderer.load_guide(path, &editor.objects) {
+            Ok(()) => println!("Loaded guide from {}", path.display()),
+            Err(err) => println!("Starting without the guide at {}: {err}", path.display()),
+        }
+    }
 
     /*
     let rect_brush =
@@ -385,8 +486,62 @@ fn main() {
 
     let mut iterations = 0;
 
-    let mut cpos = Vec2::splat(-f32::INFINITY);
-    let mut display_cascades = false;
+    let mut inspector = Inspector::default();
+    // The weights last read at the inspected position, with the position and the iteration.
+    let mut probe: Option<(Vec2<f32>, u32, ProbeWeights)> = None;
+    let mut display_settings = DisplaySettings::default();
+    let mut sequence = Sequence::Sobol;
+    let mut uniform_fraction = config.uniform_fraction;
+    let mut learner = Learner::new(Schedule::Cumulative);
+    learner.training_iterations = config.training_iterations;
+    let mut light_tracing = config.light_tracing;
+    let mut show_photons = false;
+    let mut aov = Aov::Radiance;
+    let mut denoise = config.denoise;
+
+    let mut stopping = StoppingRule {
+        max_iterations: Some(config.iterations),
+        time_budget: None,
+        target_error: None,
+    };
+    let mut render_time = Duration::ZERO;
+    let mut error = None;
+    let mut done = false;
+
+    let mut stats = Stats::new((renderer.size * renderer.size) as u64);
+    renderer.track_memory(&mut stats);
+
+    if config.headless {
+        renderer.aovs.enabled = !config.aovs.is_empty();
+        renderer.denoiser.enabled = config.denoise;
+        while !stopping.should_stop(iterations, render_time, error) {
+            render_time += time_on_device(|| {
+                renderer.render_iteration(
+                    &mut learner,
+                    &mut stats,
+                    &mut iterations,
+                    &mut error,
+                    sequence,
+                    uniform_fraction,
+                )
+            });
+            stats.log(iterations, error);
+        }
+        print_done(iterations, error);
+        let saved = save_outputs(&renderer, &config, &mut stats);
+        if let Some(path) = &config.guide {
+            renderer.save_guide(path, &editor.objects);
+        }
+        if !saved {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let app = App::new("Vlam", [config.size; 2])
+        .scale((2048 / config.size).max(1))
+        .init();
+    let mut presenter = Presenter::new(&renderer, app.display());
 
     app.run(|rt| {
         /*
@@ -418,30 +573,439 @@ fn main() {
         }
         */
 
-        if iterations < MAX_ITERS {
-            // compute_diff.dispatch_blocking([DISPLAY_SIZE / 8, DISPLAY_SIZE / 8, 1]);
-            trace_kernel.dispatch(rt.dispatch_size(), &iterations);
-            iterations += 1;
-            copy_storage.dispatch([storage.data.len() as u32, 1, 1]);
-            if iterations == MAX_ITERS {
-                println!("Done");
+        let mut reset = false;
+        if rt.key_pressed(KeyCode::Comma) || rt.key_pressed(KeyCode::Period) {
+            let size = if rt.key_pressed(KeyCode::Period) {
+                renderer.size * 2
+            } else {
+                renderer.size / 2
+            };
+            if config.supports_size(size) {
+                println!("Resolution: {size}");
+                let factor = size as f32 / renderer.size as f32;
+                editor.rescale(factor);
+                inspector.pos = inspector.pos.map(|pos| pos * factor);
+                let scene = scene.clone().scaled(size as f32 / config.size as f32);
+                renderer = Renderer::new(&config, &scene, &editor.objects, size);
+                presenter = Presenter::new(&renderer, rt.display());
+                stats = Stats::new((size * size) as u64);
+                renderer.track_memory(&mut stats);
+                reset = true;
+            }
+        }
+        // The testbed doesn't report window resizes and draws to the display it created at
+        // launch, so a resized window only stretches it; should it replace the display, the
+        // presenter follows its size.
+        if !presenter.fits(rt.display()) {
+            presenter = Presenter::new(&renderer, rt.display());
+        }
+        let cursor = presenter.to_render(&renderer, rt.cursor_position);
+        if rt.button_pressed(MouseButton::Left) {
+            editor.select(cursor);
+        } else if rt.button_down(MouseButton::Left) {
+            reset |= editor.drag(cursor);
+        }
+        // Each line scrolled scales the selection by 10%.
+        if rt.scroll_delta.y != 0.0 {
+            reset |= editor.scale_radius(1.1_f32.powf(rt.scroll_delta.y));
+        }
+        if rt.key_pressed(KeyCode::BracketRight) {
+            reset |= editor.scale_radius(1.1);
+        }
+        if rt.key_pressed(KeyCode::BracketLeft) {
+            reset |= editor.scale_radius(1.0 / 1.1);
+        }
+        for (key, delta) in [(KeyCode::KeyI, 0.05), (KeyCode::KeyK, -0.05)] {
+            if rt.key_pressed(key)
+                && let Some(ior) = editor.change_ior(delta)
+            {
+                println!("IOR: {ior:.2}");
+                reset = true;
+            }
+        }
+        if rt.key_pressed(KeyCode::KeyC) {
+            reset |= editor.cycle_material();
+            println!("Material: {}", editor.material_name());
+        }
+        if rt.key_pressed(KeyCode::KeyG)
+            && let Some(roughness) = editor.cycle_roughness()
+        {
+            println!("Roughness: {roughness:.2}");
+            reset = true;
+        }
+        if rt.key_pressed(KeyCode::KeyN) {
+            if editor.objects.len() < renderer.world.capacity() {
+                editor.add(cursor);
+                reset = true;
+            } else {
+                println!("Object limit reached: {}", renderer.world.capacity());
+            }
+        }
+        if rt.key_pressed(KeyCode::Delete) || rt.key_pressed(KeyCode::Backspace) {
+            reset |= editor.delete();
+        }
+        if rt.key_pressed(KeyCode::KeyM) {
+            let i = UNIFORM_FRACTIONS
+                .iter()
+                .position(|&x| x == uniform_fraction)
+                .unwrap_or(0);
+            uniform_fraction = UNIFORM_FRACTIONS[(i + 1) % UNIFORM_FRACTIONS.len()];
+            println!("Uniform fraction: {}", uniform_fraction);
+            reset = true;
+        }
+        if rt.key_pressed(KeyCode::KeyL) {
+            let i = Schedule::PRESETS
+                .iter()
+                .position(|&x| x == learner.schedule)
+                .unwrap_or(0);
+            learner.schedule = Schedule::PRESETS[(i + 1) % Schedule::PRESETS.len()];
+            println!("Learning schedule: {:?}", learner.schedule);
+            reset = true;
+        }
+        if rt.key_pressed(KeyCode::KeyJ) {
+            let i = TRAINING_ITERATIONS
+                .iter()
+                .position(|&x| x == learner.training_iterations)
+                .unwrap_or(0);
+            learner.training_iterations = TRAINING_ITERATIONS[(i + 1) % TRAINING_ITERATIONS.len()];
+            println!("Training iterations: {}", learner.training_iterations);
+            reset = true;
+        }
+        if rt.key_pressed(KeyCode::KeyO) {
+            if config.tracer == TracerKind::Voxel {
+                println!("Light tracing only sees the analytic objects, not the voxel world");
+            } else {
+                light_tracing = light_tracing.next();
+                println!("Light tracing: {:?}", light_tracing);
+            }
+        }
+        if rt.key_pressed(KeyCode::KeyH) {
+            if config.tracer == TracerKind::Voxel {
+                println!("Photon mapping only sees the analytic objects, not the voxel world");
+            } else {
+                show_photons ^= true;
+                println!("Photon mapping: {}", show_photons);
+            }
+        }
+        if rt.key_pressed(KeyCode::KeyV) {
+            aov = aov.next();
+            println!("Showing: {}", aov.name());
+        }
+        if rt.key_pressed(KeyCode::KeyS) {
+            sequence = sequence.next();
+            println!("Sequence: {:?}", sequence);
+            reset = true;
+        }
+        if reset {
+            iterations = 0;
+            stats.clear_samples();
+            render_time = Duration::ZERO;
+            error = None;
+            learner.reset();
+            renderer.reset(&editor.objects, &learner);
+            probe = None;
+        }
+
+        if rt.key_pressed(KeyCode::KeyA) {
+            renderer.toggle_adaptive_sampling();
+        }
+        if rt.key_pressed(KeyCode::KeyR) {
+            stopping.cycle_target_error();
+        }
+        if rt.key_pressed(KeyCode::KeyY) {
+            stopping.cycle_time_budget();
+        }
+        if rt.key_pressed(KeyCode::KeyB) {
+            denoise ^= true;
+            println!("Denoising: {}", denoise);
+        }
+        renderer.denoiser.enabled = denoise;
+        let was_done = done;
+        done = stopping.should_stop(iterations, render_time, error);
+        if done && !was_done {
+            print_done(iterations, error);
+            save_outputs(&renderer, &config, &mut stats);
+            if let Some(path) = &config.guide {
+                renderer.save_guide(path, &editor.objects);
             }
         }
 
-        draw_kernel.dispatch(rt.dispatch_size(), &iterations);
+        if !done {
+            renderer.aovs.enabled = aov != Aov::Radiance || !config.aovs.is_empty();
+            render_time += time_on_device(|| {
+                renderer.render_iteration(
+                    &mut learner,
+                    &mut stats,
+                    &mut iterations,
+                    &mut error,
+                    sequence,
+                    uniform_fraction,
+                );
+                if light_tracing != LightTracing::Off {
+                    renderer.trace_light(&mut stats);
+                }
+                if show_photons {
+                    renderer.map_photons(&mut stats);
+                }
+            });
+            stats.log(iterations, error);
+        }
+        if rt.key_pressed(KeyCode::KeyP) {
+            stats.profiling ^= true;
+            println!("Profiling: {}", stats.profiling);
+        }
 
-        if rt.key_pressed(KeyCode::KeyQ) {
-            display_cascades ^= true;
-            cpos = rt.cursor_position;
+        if rt.key_pressed(KeyCode::KeyT) {
+            display_settings.tonemapper = display_settings.tonemapper.next();
+            println!("Tonemapper: {:?}", display_settings.tonemapper);
+        }
+        if rt.key_pressed(KeyCode::Equal) {
+            display_settings.exposure_ev += 0.5;
+            println!("Exposure: {:+.1} EV", display_settings.exposure_ev);
+        }
+        if rt.key_pressed(KeyCode::Minus) {
+            display_settings.exposure_ev -= 0.5;
+            println!("Exposure: {:+.1} EV", display_settings.exposure_ev);
+        }
+        if rt.key_pressed(KeyCode::KeyX) {
+            display_settings.auto_exposure ^= true;
+        }
+        if rt.key_pressed(KeyCode::KeyF) {
+            display_settings.false_color ^= true;
         }
+        if display_settings.auto_exposure && iterations > 0 {
+            renderer.update_histogram();
+        }
+        presenter.draw(
+            &renderer,
+            &mut stats,
+            &display_settings,
+            light_tracing,
+            show_photons,
+            aov,
+        );
 
-        if display_cascades {
-            draw_rc_overlay.dispatch(rt.dispatch_size(), &cpos, &10.0);
+        if rt.key_pressed(KeyCode::KeyQ) {
+            inspector.toggle(cursor);
         }
-        if rt.key_down(KeyCode::KeyW) {
-            for i in 4..storage.num_cascades {
-                draw_probe_overlay.dispatch([DISPLAY_SIZE, DISPLAY_SIZE, 1], &i);
+        let digits = [
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+        ];
+        for (i, &key) in digits.iter().enumerate() {
+            let cascade = i as u32;
+            if rt.key_pressed(key) && cascade < renderer.storage.num_cascades {
+                inspector.toggle_cascade(cascade);
+                let shown = if inspector.shows(cascade) {
+                    "shown"
+                } else {
+                    "hidden"
+                };
+                println!("Cascade {cascade}: {shown}");
+            }
+        }
+        if let Some(pos) = inspector.pos {
+            let requested = [KeyCode::KeyQ, KeyCode::KeyZ, KeyCode::KeyD]
+                .into_iter()
+                .any(|key| rt.key_pressed(key));
+            let stale = probe.as_ref().is_none_or(|(read_pos, read_at, _)| {
+                *read_pos != pos || iterations.abs_diff(*read_at) >= PROBE_INTERVAL
+            });
+            // Reading the weights back stalls the device, so it's only done when they changed.
+            if requested || stale {
+                probe = Some((pos, iterations, ProbeWeights::read(&renderer.storage, pos)));
+            }
+            let (_, _, probe) = probe.as_ref().unwrap();
+            if rt.key_pressed(KeyCode::KeyQ) || rt.key_pressed(KeyCode::KeyZ) {
+                probe.print(&inspector);
+            }
+            if rt.key_pressed(KeyCode::KeyD) {
+                let path = "probe.csv";
+                match std::fs::File::create(path).and_then(|mut file| probe.write_csv(&mut file)) {
+                    Ok(()) => println!("Saved {path}"),
+                    Err(err) => eprintln!("Failed to save {path}: {err}"),
+                }
             }
+            presenter.draw_inspector(&renderer, &inspector, probe);
+        }
+        if rt.key_down(KeyCode::KeyW) {
+            presenter.draw_probes(&renderer, &inspector);
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{pcgf, pcgf_host};
+
+    /// Evaluates `f` for each index in `0..n` on the tests' CPU [`DEVICE`](super::DEVICE), so the
+    /// device side of these tests and those in `utils` runs without a GPU.
+    pub fn eval<T: Value>(n: u32, f: impl Fn(Expr<u32>) -> Expr<T>) -> Vec<T> {
+        let out = DEVICE.create_buffer::<T>(n as usize);
+        DEVICE
+            .create_kernel::<fn()>(&track!(|| {
+                let i = dispatch_id().x;
+                out.write(i, f(i));
+            }))
+            .dispatch([n, 1, 1]);
+        out.copy_to_vec()
+    }
+
+    fn random_fluence(seed: u32) -> Fluence {
+        let v = |i| pcgf_host(seed * 8 + i);
+        Fluence {
+            radiance: Vec3::new(v(0) * 5.0, v(1) * 5.0, v(2) * 5.0),
+            transmittance: Vec3::new(v(3), v(4), v(5)),
+        }
+    }
+
+    /// Device version of [`random_fluence`].
+    #[tracked]
+    fn random_fluence_expr(seed: Expr<u32>) -> Expr<Fluence> {
+        let v = |i: u32| pcgf(seed * 8 + i);
+        Fluence::expr(
+            Vec3::expr(v(0), v(1), v(2)) * 5.0,
+            Vec3::expr(v(3), v(4), v(5)),
+        )
+    }
+
+    fn assert_close(a: Vec3<f32>, b: Vec3<f32>) {
+        for (a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z)] {
+            assert!((a - b).abs() <= 1e-5 * (1.0 + a.abs()), "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn over_is_associative() {
+        for seed in 0..100 {
+            let [a, b, c] = [0, 1, 2].map(|i| random_fluence(3 * seed + i));
+            let left = a.over(b).over(c);
+            let right = a.over(b.over(c));
+            assert_close(left.radiance, right.radiance);
+            assert_close(left.transmittance, right.transmittance);
+        }
+        let triple = |seed: Expr<u32>| [0, 1, 2].map(|i| random_fluence_expr(seed * 3 + i));
+        let left = eval(100, |seed| {
+            let [a, b, c] = triple(seed);
+            a.over(b).over(c)
+        });
+        let right = eval(100, |seed| {
+            let [a, b, c] = triple(seed);
+            a.over(b.over(c))
+        });
+        for (left, right) in left.into_iter().zip(right) {
+            assert_close(left.radiance, right.radiance);
+            assert_close(left.transmittance, right.transmittance);
+        }
+    }
+
+    #[test]
+    fn over_empty_is_identity() {
+        for seed in 0..100 {
+            let a = random_fluence(seed);
+            for fluence in [a.over(Fluence::empty()), Fluence::empty().over(a)] {
+                assert_close(fluence.radiance, a.radiance);
+                assert_close(fluence.transmittance, a.transmittance);
+            }
+        }
+        let empty = Fluence::empty();
+        let before = eval(100, |seed| random_fluence_expr(seed).over(empty.expr()));
+        let after = eval(100, |seed| empty.expr().over(random_fluence_expr(seed)));
+        for (seed, (before, after)) in before.into_iter().zip(after).enumerate() {
+            let a = random_fluence(seed as u32);
+            for fluence in [before, after] {
+                assert_close(fluence.radiance, a.radiance);
+                assert_close(fluence.transmittance, a.transmittance);
+            }
+        }
+    }
+
+    #[test]
+    fn device_over_matches_host() {
+        let device = eval(100, |i| {
+            random_fluence_expr(i).over(random_fluence_expr(i + 100))
+        });
+        for (i, fluence) in device.into_iter().enumerate() {
+            let host = random_fluence(i as u32).over(random_fluence(i as u32 + 100));
+            assert_close(fluence.radiance, host.radiance);
+            assert_close(fluence.transmittance, host.transmittance);
+        }
+    }
+
+    #[test]
+    fn to_fluence_is_beer_lambert() {
+        let colors = [
+            Color::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.5, 4.0)),
+            Color::new(Vec3::splat(20.0), Vec3::splat(2.0)),
+            Color::empty(),
+        ];
+        let lengths = [0.0, 0.25, 1.0, 7.5];
+        let device = eval((colors.len() * lengths.len()) as u32, |i| {
+            let color = colors.expr().read(i / lengths.len() as u32);
+            let length = lengths.expr().read(i % lengths.len() as u32);
+            color.to_fluence(length)
+        });
+        for (i, fluence) in device.into_iter().enumerate() {
+            let color = colors[i / lengths.len()];
+            let length = lengths[i % lengths.len()];
+            let transmittance = color.opacity.map(|x| (-x * length).exp());
+            let radiance = Vec3::new(
+                color.emission.x * (1.0 - transmittance.x),
+                color.emission.y * (1.0 - transmittance.y),
+                color.emission.z * (1.0 - transmittance.z),
+            );
+            assert_close(fluence.transmittance, transmittance);
+            assert_close(fluence.radiance, radiance);
+        }
+    }
+
+    #[test]
+    fn bilinear_weights_sum_to_one() {
+        let device = eval(1000, |i| {
+            let pos = Vec2::expr(pcgf(2 * i), pcgf(2 * i + 1)) * 100.0;
+            bilinear(pos)
+                .into_iter()
+                .map(|(_, w)| w)
+                .reduce(|a, b| a + b)
+                .unwrap()
+        });
+        for sum in device {
+            assert!((sum - 1.0).abs() < 1e-5, "weights sum to {sum}");
+        }
+    }
+
+    #[test]
+    fn cascade_index_is_bijective() {
+        let storage = CascadeStorage {
+            data: DEVICE.create_buffer(1),
+            base_size: Vec2::new(16, 8),
+            base_spacing: 1.0,
+            base_angles: 4,
+            angular_scale: 2,
+            num_cascades: 3,
+        };
+        for cascade in 0..storage.num_cascades {
+            let size = Vec2::new(
+                storage.base_size.x >> cascade,
+                storage.base_size.y >> cascade,
+            );
+            let angles = storage.base_angles << (cascade * storage.angular_scale);
+            let mut indices = eval(size.x * size.y * angles, |i| {
+                let angle = i % angles;
+                let pos = Vec2::expr(i / angles / size.y, i / angles % size.y);
+                storage.index(cascade.expr(), pos, angle)
+            });
+            indices.sort_unstable();
+            let start = cascade * storage.cascade_size();
+            assert_eq!(
+                indices,
+                (start..start + storage.cascade_size()).collect::<Vec<_>>()
+            );
+        }
+    }
+}
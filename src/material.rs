@@ -0,0 +1,146 @@
+use super::*;
+
+/// Optical properties shared by voxel cells and analytic objects.
+#[derive(Clone, Copy, Debug, PartialEq, Value)]
+#[repr(C)]
+pub struct Material {
+    /// Radiance of an optically thick volume of the material.
+    pub emission: Emission,
+    /// Absorption coefficient per unit length.
+    pub absorption: Opacity,
+    /// Scattering coefficient per unit length. Scattered light is only removed from the ray;
+    /// in-scattering isn't traced.
+    pub scattering: Opacity,
+    /// Diffuse reflectance; if nonzero, the boundary is an opaque Lambertian surface.
+    pub albedo: Albedo,
+    pub ior: f32,
+    /// Roughness of the refracting and reflecting interfaces of analytic objects,
+    /// from 0 for smooth to 1.
+    pub roughness: f32,
+}
+impl Material {
+    pub fn empty() -> Self {
+        Material {
+            emission: Vec3::splat(0.0),
+            absorption: Vec3::splat(0.0),
+            scattering: Vec3::splat(0.0),
+            albedo: Vec3::splat(0.0),
+            ior: 1.0,
+            roughness: 0.0,
+        }
+    }
+    /// An emitting and absorbing volume, matching `Color::new(emission, opacity)`.
+    pub fn new(emission: Emission, absorption: Opacity) -> Self {
+        Material {
+            emission,
+            absorption,
+            ..Material::empty()
+        }
+    }
+    pub fn solid(emission: Emission) -> Self {
+        Material::new(emission, Vec3::splat(999999.0))
+    }
+    /// An opaque, non-emissive diffuse reflector.
+    pub fn diffuse(albedo: Albedo) -> Self {
+        Material {
+            albedo,
+            ..Material::solid(Vec3::splat(0.0))
+        }
+    }
+    /// A clear refracting medium.
+    pub fn glass(ior: f32) -> Self {
+        Material {
+            ior,
+            ..Material::empty()
+        }
+    }
+    pub fn with_ior(self, ior: f32) -> Self {
+        Material { ior, ..self }
+    }
+    pub fn with_roughness(self, roughness: f32) -> Self {
+        Material { roughness, ..self }
+    }
+    pub fn is_diffuse(&self) -> bool {
+        self.albedo.x > 0.0 || self.albedo.y > 0.0 || self.albedo.z > 0.0
+    }
+    /// The volume's emission and extinction, as used for tracing through it.
+    pub fn color(&self) -> Color {
+        Color::new(
+            self.emission,
+            Vec3::new(
+                self.absorption.x + self.scattering.x,
+                self.absorption.y + self.scattering.y,
+                self.absorption.z + self.scattering.z,
+            ),
+        )
+    }
+}
+impl MaterialExpr {
+    #[tracked]
+    pub fn color(self) -> Expr<Color> {
+        Color::expr(self.emission, self.absorption + self.scattering)
+    }
+    #[tracked]
+    pub fn is_diffuse(self) -> Expr<bool> {
+        (self.albedo > 0.0).any()
+    }
+}
+
+/// The named materials offered for editing, in both the voxel and analytic worlds.
+pub fn library() -> [(&'static str, Material); 6] {
+    [
+        ("glass", Material::glass(1.5)),
+        ("frosted glass", Material::glass(1.5).with_roughness(0.3)),
+        (
+            "smoke",
+            Material::new(Vec3::splat(0.0), Vec3::new(0.05, 0.02, 0.005)),
+        ),
+        ("light", Material::new(Vec3::splat(20.0), Vec3::splat(2.0))),
+        ("black", Material::solid(Vec3::splat(0.0))),
+        ("white", Material::diffuse(Vec3::splat(0.8))),
+    ]
+}
+
+/// Index into a [`MaterialTable`].
+pub type MaterialId = u32;
+
+/// Number of materials a [`MaterialTable`] has room for by default.
+pub const MAX_MATERIALS: usize = 256;
+
+/// Device copy of a list of materials, indexed by [`MaterialId`].
+pub struct MaterialTable {
+    materials: Buffer<Material>,
+}
+impl MaterialTable {
+    pub fn new(materials: &[Material]) -> Self {
+        let this = Self {
+            materials: DEVICE.create_buffer(MAX_MATERIALS.max(materials.len())),
+        };
+        this.upload(materials);
+        this
+    }
+    pub fn capacity(&self) -> usize {
+        self.materials.len()
+    }
+    pub fn memory_bytes(&self) -> usize {
+        self.capacity() * std::mem::size_of::<Material>()
+    }
+    /// Replaces the materials on the device; ids past the end are left as they were.
+    pub fn upload(&self, materials: &[Material]) {
+        assert!(
+            materials.len() <= self.capacity(),
+            "MaterialTable has room for {} materials, got {}",
+            self.capacity(),
+            materials.len()
+        );
+        if !materials.is_empty() {
+            self.materials.view(..materials.len()).copy_from(materials);
+        }
+    }
+    pub fn download(&self) -> Vec<Material> {
+        self.materials.copy_to_vec()
+    }
+    pub fn read(&self, id: Expr<MaterialId>) -> Expr<Material> {
+        self.materials.read(id)
+    }
+}
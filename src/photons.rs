@@ -0,0 +1,205 @@
+use std::f32::consts::PI;
+
+use super::*;
+use crate::light::{Emitters, MAX_STEPS, STEP, WEIGHT_CUTOFF};
+use crate::utils::{pcg, pcg3d};
+
+/// Photons deposited per unit length of path, on average.
+const DEPOSIT_RATE: f32 = 1.0 / 16.0;
+const TABLE_SIZE: u32 = 1 << 20;
+const EMPTY: u32 = u32::MAX;
+/// Radius reduction parameter of progressive photon mapping (Hachisuka et al. 2008).
+const ALPHA: f32 = 2.0 / 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Value)]
+#[repr(C)]
+pub struct Photon {
+    pub pos: Vec2<f32>,
+    pub power: Radiance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Value)]
+#[repr(C)]
+struct GatherPoint {
+    radius_sqr: f32,
+    count: f32,
+    flux: Radiance,
+}
+
+/// Progressive photon mapping of the lights, as a consistent alternative to the guided estimator.
+///
+/// Photons are deposited along paths from the [`Emitters`], each representing a length of path,
+/// so dividing the gathered power by the gather area estimates angle-averaged radiance.
+/// The pixel centers are the gather points, with radii shrinking every pass.
+///
+/// Only the objects of an [`AnalyticTracer`] are supported, both as emitters and as the world the
+/// photons travel through; the voxel world isn't seen, so the viewer doesn't offer the photon map
+/// with the voxel tracer.
+pub struct PhotonMap {
+    photons: Buffer<Photon>,
+    // Hash grid over cells of size `initial_radius`, as linked lists through `next`.
+    heads: Buffer<u32>,
+    next: Buffer<u32>,
+    count: Buffer<u32>,
+    gather: Buffer<GatherPoint>,
+    capacity: u32,
+    size: Vec2<u32>,
+    initial_radius: f32,
+    pub paths: u32,
+    pub passes: u32,
+}
+impl PhotonMap {
+    pub fn new(size: Vec2<u32>, paths: u32, capacity: u32, initial_radius: f32) -> Self {
+        let this = Self {
+            photons: DEVICE.create_buffer(capacity as usize),
+            heads: DEVICE.create_buffer_from_fn(TABLE_SIZE as usize, |_| EMPTY),
+            next: DEVICE.create_buffer(capacity as usize),
+            count: DEVICE.create_buffer_from_fn(1, |_| 0),
+            gather: DEVICE.create_buffer((size.x * size.y) as usize),
+            capacity,
+            size,
+            initial_radius,
+            paths,
+            passes: 0,
+        };
+        this.reset_gather();
+        this
+    }
+    fn reset_gather(&self) {
+        self.gather.copy_from(&vec![
+            GatherPoint {
+                radius_sqr: self.initial_radius * self.initial_radius,
+                count: 0.0,
+                flux: Vec3::splat(0.0),
+            };
+            self.gather.len()
+        ]);
+    }
+    pub fn clear(&mut self) {
+        self.reset_gather();
+        self.passes = 0;
+    }
+    /// Empties the photon map before a pass.
+    pub fn begin_pass(&self) {
+        self.heads.copy_from(&vec![EMPTY; TABLE_SIZE as usize]);
+        self.count.copy_from(&[0]);
+    }
+    #[tracked]
+    fn cell_hash(&self, cell: Expr<Vec2<i32>>) -> Expr<u32> {
+        pcg(cell.x.cast_u32() ^ pcg(cell.y.cast_u32())) % TABLE_SIZE
+    }
+    #[tracked]
+    fn cell(&self, pos: Expr<Vec2<f32>>) -> Expr<Vec2<i32>> {
+        (pos / self.initial_radius).floor().cast_i32()
+    }
+    #[tracked]
+    fn store(&self, pos: Expr<Vec2<f32>>, power: Expr<Radiance>) {
+        let index = self.count.atomic_fetch_add(0, 1);
+        // Photons past the capacity are dropped, which biases the estimate; the capacity should
+        // be set comfortably above `paths / DEPOSIT_RATE` times the mean path length.
+        if index < self.capacity {
+            self.photons
+                .write(index, Photon::from_comps_expr(PhotonComps { pos, power }));
+            let head = self
+                .heads
+                .atomic_exchange(self.cell_hash(self.cell(pos)), index);
+            self.next.write(index, head);
+        }
+    }
+    /// Traces one photon path; dispatched over `paths` threads per pass.
+    #[tracked]
+    pub fn emit(&self, world: &AnalyticTracer, emitters: &Emitters, seed: Expr<u32>) {
+        let path = dispatch_id().x;
+        // Each step hashes `2 * step` for its distances and `2 * step + 1` for its bounces, so the
+        // emission takes the values after the last step.
+        let rand = pcg3df(Vec3::expr(path, seed, 2 * MAX_STEPS));
+        let rand2 = pcg3df(Vec3::expr(path, seed, 2 * MAX_STEPS + 1));
+
+        if emitters.total_power() > 0.0 {
+            let (pos, dir, weight) = emitters.sample(world, rand, rand2);
+            let pos = pos.var();
+            let dir = dir.var();
+            let weight = (weight / self.paths as f32).var();
+
+            for step in 0_u32.expr()..MAX_STEPS.expr() {
+                let rand = pcg3df(Vec3::expr(path, seed, 2 * step));
+                let bounce_seed = pcg3d(Vec3::expr(path, seed, 2 * step + 1)).x;
+                let traced = world.trace(**pos, **dir, (rand.x * STEP).expr(), bounce_seed);
+                let deposit_pos = traced.final_pos;
+                let deposit_weight = weight * traced.fluence.transmittance;
+                let traced = world.trace(
+                    deposit_pos,
+                    traced.final_dir,
+                    ((1.0 - rand.x) * STEP).expr(),
+                    pcg(bounce_seed),
+                );
+                if rand.y < DEPOSIT_RATE * STEP {
+                    self.store(deposit_pos, deposit_weight / DEPOSIT_RATE);
+                }
+                *weight = deposit_weight * traced.fluence.transmittance;
+                *pos = traced.final_pos;
+                *dir = traced.final_dir;
+                if weight.reduce_max() < WEIGHT_CUTOFF
+                    || (pos < 0.0).any()
+                    || (pos >= self.size.expr().cast_f32()).any()
+                {
+                    break;
+                }
+            }
+        }
+    }
+    /// Gathers the photons around `pixel` and updates its radius; dispatched over the display
+    /// after every [`Self::emit`] pass.
+    #[tracked]
+    pub fn gather(&self, pixel: Expr<Vec2<u32>>) {
+        let index = pixel.x + pixel.y * self.size.x;
+        let point = self.gather.read(index);
+        let pos = pixel.cast_f32() + 0.5;
+        let cell = self.cell(pos);
+
+        let count = 0.0_f32.var();
+        let flux = Vec3::splat(0.0_f32).var();
+        for dx in -1_i32..=1_i32 {
+            for dy in -1_i32..=1_i32 {
+                let neighbor = cell + Vec2::expr(dx, dy);
+                let photon_index = self.heads.read(self.cell_hash(neighbor)).var();
+                loop {
+                    if photon_index == EMPTY {
+                        break;
+                    }
+                    let photon = self.photons.read(photon_index);
+                    // Neighboring cells can share a hash and so a list, whose photons must only be
+                    // counted from the cell they were stored in.
+                    if (self.cell(photon.pos) == neighbor).all()
+                        && (photon.pos - pos).length_squared() < point.radius_sqr
+                    {
+                        *count += 1.0;
+                        *flux += photon.power;
+                    }
+                    *photon_index = self.next.read(photon_index);
+                }
+            }
+        }
+
+        let new_count = point.count + ALPHA * count;
+        let ratio = if point.count + count > 0.0 {
+            new_count / (point.count + count)
+        } else {
+            1.0_f32.expr()
+        };
+        self.gather.write(
+            index,
+            GatherPoint::from_comps_expr(GatherPointComps {
+                radius_sqr: point.radius_sqr * ratio,
+                count: new_count,
+                flux: (point.flux + flux) * ratio,
+            }),
+        );
+    }
+    /// Estimate at `pixel` after `passes` passes.
+    #[tracked]
+    pub fn mean(&self, pixel: Expr<Vec2<u32>>, passes: Expr<u32>) -> Expr<Radiance> {
+        let point = self.gather.read(pixel.x + pixel.y * self.size.x);
+        point.flux / (PI * point.radius_sqr * keter::max(passes, 1).cast_f32())
+    }
+}
@@ -0,0 +1,266 @@
+//! Seeded procedural scenes for benchmarks and regression tests, in a world of width 1024.
+//! The same seed always gives the same scene.
+
+use std::cmp::Ordering;
+
+use palette::{FromColor, LinSrgb, Oklch};
+
+use super::*;
+use crate::material::library;
+use crate::scene::Draw;
+use crate::utils::{pcg_host, pcgf_host};
+
+const SIZE: f32 = 1024.0;
+/// Hues lights are drawn from, so scenes with many lights still have few materials.
+const LIGHT_HUES: u32 = 12;
+
+struct Rng {
+    seed: u32,
+    index: u32,
+}
+impl Rng {
+    fn new(seed: u32) -> Self {
+        Self {
+            seed: pcg_host(seed),
+            index: 0,
+        }
+    }
+    fn next(&mut self) -> f32 {
+        self.index += 1;
+        pcgf_host(self.seed ^ pcg_host(self.index))
+    }
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+    /// Uniform in `0..n`.
+    fn below(&mut self, n: u32) -> u32 {
+        ((self.next() * n as f32) as u32).min(n - 1)
+    }
+    /// Log-uniform in `min..max`, so small sizes are as common per octave as large ones.
+    fn log_range(&mut self, min: f32, max: f32) -> f32 {
+        min * (max / min).powf(self.next())
+    }
+    fn pos(&mut self) -> Vec2<f32> {
+        Vec2::new(self.next() * SIZE, self.next() * SIZE)
+    }
+    fn hue(&mut self, lightness: f64) -> Vec3<f32> {
+        let turns = self.next() as f64;
+        oklch(lightness, turns)
+    }
+    /// One of `hues` evenly spaced hues.
+    fn palette_hue(&mut self, lightness: f64, hues: u32) -> Vec3<f32> {
+        let turns = self.below(hues) as f64 / hues as f64;
+        oklch(lightness, turns)
+    }
+}
+
+/// Linear color of the given lightness and hue, in turns, with a fixed chroma.
+fn oklch(lightness: f64, turns: f64) -> Vec3<f32> {
+    let color = LinSrgb::from_color(Oklch::new(lightness, 0.15, turns * 360.0));
+    Vec3::new(
+        color.red.max(0.0) as f32,
+        color.green.max(0.0) as f32,
+        color.blue.max(0.0) as f32,
+    )
+}
+
+fn light(rng: &mut Rng, center: Vec2<f32>, radius: f32) -> Draw {
+    let emission = rng.palette_hue(0.8, LIGHT_HUES);
+    Draw {
+        brush: Brush::Circle(radius),
+        center,
+        material: Material::new(
+            Vec3::new(emission.x * 20.0, emission.y * 20.0, emission.z * 20.0),
+            Vec3::splat(100.0),
+        ),
+    }
+}
+
+impl Scene {
+    /// `count` circles with log-uniform radii between `min_radius` and `max_radius`,
+    /// each a random [`library`] material; one in ten is a light.
+    pub fn circle_field(count: u32, min_radius: f32, max_radius: f32, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let materials = library();
+        let draws = (0..count)
+            .map(|_| {
+                let center = rng.pos();
+                let radius = rng.log_range(min_radius, max_radius);
+                if rng.next() < 0.1 {
+                    light(&mut rng, center, radius)
+                } else {
+                    Draw {
+                        brush: Brush::Circle(radius),
+                        center,
+                        material: materials[rng.below(materials.len() as u32) as usize].1,
+                    }
+                }
+            })
+            .collect();
+        Self { draws }
+    }
+    /// A `cells` by `cells` maze of white walls, carved by a randomized depth-first search,
+    /// with `lights` small lights in random cells.
+    pub fn maze(cells: u32, lights: u32, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let cell = SIZE / cells as f32;
+        let thickness = (cell / 16.0).max(1.0);
+        let index = |x: u32, y: u32| (x + y * cells) as usize;
+
+        // Open passages to the right of and below each cell.
+        let mut right = vec![false; (cells * cells) as usize];
+        let mut down = vec![false; (cells * cells) as usize];
+        let mut visited = vec![false; (cells * cells) as usize];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some(&(x, y)) = stack.last() {
+            let neighbors = [
+                (x > 0).then(|| (x - 1, y)),
+                (x + 1 < cells).then(|| (x + 1, y)),
+                (y > 0).then(|| (x, y - 1)),
+                (y + 1 < cells).then(|| (x, y + 1)),
+            ]
+            .into_iter()
+            .flatten()
+            .filter(|&(nx, ny)| !visited[index(nx, ny)])
+            .collect::<Vec<_>>();
+            if neighbors.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let (nx, ny) = neighbors[rng.below(neighbors.len() as u32) as usize];
+            match (nx.cmp(&x), ny.cmp(&y)) {
+                (Ordering::Less, _) => right[index(nx, ny)] = true,
+                (Ordering::Greater, _) => right[index(x, y)] = true,
+                (_, Ordering::Less) => down[index(nx, ny)] = true,
+                _ => down[index(x, y)] = true,
+            }
+            visited[index(nx, ny)] = true;
+            stack.push((nx, ny));
+        }
+
+        let wall = Material::diffuse(Vec3::splat(0.8));
+        let mut draws = vec![];
+        let mut push = |center: Vec2<f32>, half_size: Vec2<f32>| {
+            draws.push(Draw {
+                brush: Brush::Rect(half_size.x, half_size.y),
+                center,
+                material: wall,
+            })
+        };
+        push(Vec2::new(SIZE / 2.0, 0.0), Vec2::new(SIZE / 2.0, thickness));
+        push(
+            Vec2::new(SIZE / 2.0, SIZE),
+            Vec2::new(SIZE / 2.0, thickness),
+        );
+        push(Vec2::new(0.0, SIZE / 2.0), Vec2::new(thickness, SIZE / 2.0));
+        push(
+            Vec2::new(SIZE, SIZE / 2.0),
+            Vec2::new(thickness, SIZE / 2.0),
+        );
+        for y in 0..cells {
+            for x in 0..cells {
+                let corner = Vec2::new(x as f32 + 1.0, y as f32 + 1.0) * cell;
+                if x + 1 < cells && !right[index(x, y)] {
+                    push(
+                        Vec2::new(corner.x, corner.y - cell / 2.0),
+                        Vec2::new(thickness, cell / 2.0 + thickness),
+                    );
+                }
+                if y + 1 < cells && !down[index(x, y)] {
+                    push(
+                        Vec2::new(corner.x - cell / 2.0, corner.y),
+                        Vec2::new(cell / 2.0 + thickness, thickness),
+                    );
+                }
+            }
+        }
+        for _ in 0..lights {
+            let center = Vec2::new(
+                (rng.below(cells) as f32 + 0.5) * cell,
+                (rng.below(cells) as f32 + 0.5) * cell,
+            );
+            draws.push(light(&mut rng, center, cell / 8.0));
+        }
+        Self { draws }
+    }
+    /// `count` overlapping blobs of tinted, scattering fog around `lights` lights.
+    pub fn fog(count: u32, lights: u32, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut draws = (0..count)
+            .map(|_| {
+                let tint = rng.hue(0.7);
+                let density = rng.log_range(0.005, 0.1);
+                Draw {
+                    brush: Brush::Circle(rng.log_range(40.0, 160.0)),
+                    center: rng.pos(),
+                    material: Material {
+                        absorption: Vec3::new(1.0 - tint.x, 1.0 - tint.y, 1.0 - tint.z)
+                            .map(|x| x.max(0.0) * density),
+                        scattering: Vec3::splat(density),
+                        ..Material::empty()
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..lights {
+            let center = rng.pos();
+            draws.push(light(&mut rng, center, 6.0));
+        }
+        Self { draws }
+    }
+    /// `count` small lights of random colors from a palette, scattered uniformly.
+    pub fn emitters(count: u32, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let draws = (0..count)
+            .map(|_| {
+                let center = rng.pos();
+                let radius = rng.range(1.0, 4.0);
+                light(&mut rng, center, radius)
+            })
+            .collect();
+        Self { draws }
+    }
+    /// The procedural scenes at their stress test sizes, by name.
+    pub fn stress_tests(seed: u32) -> Vec<(&'static str, Scene)> {
+        vec![
+            ("circle_field", Scene::circle_field(200, 2.0, 64.0, seed)),
+            ("maze", Scene::maze(16, 8, seed)),
+            ("fog", Scene::fog(64, 4, seed)),
+            ("emitters", Scene::emitters(200, seed)),
+        ]
+    }
+    /// The scene as analytic objects. Rectangles become rows of circles as wide as their short
+    /// side, so the two tracers only roughly agree on scenes with rectangles.
+    pub fn objects(&self) -> Vec<Object> {
+        let mut objects = vec![];
+        for draw in &self.draws {
+            match draw.brush {
+                Brush::Circle(radius) => objects.push(Object {
+                    center: draw.center,
+                    radius,
+                    material: draw.material,
+                }),
+                Brush::Rect(width, height) => {
+                    let radius = width.min(height);
+                    let (length, axis) = if width >= height {
+                        (width, Vec2::new(1.0, 0.0))
+                    } else {
+                        (height, Vec2::new(0.0, 1.0))
+                    };
+                    // Enough circles that neighbors are at most a diameter apart.
+                    let count = (length / radius).ceil().max(2.0) as u32;
+                    let spacing = 2.0 * (length - radius) / (count - 1) as f32;
+                    for i in 0..count {
+                        objects.push(Object {
+                            center: draw.center + axis * (radius - length + spacing * i as f32),
+                            radius,
+                            material: draw.material,
+                        });
+                    }
+                }
+            }
+        }
+        objects
+    }
+}
@@ -0,0 +1,483 @@
+//! Plain host implementations of the tracers, used to check the device versions.
+
+use super::*;
+use crate::analytic::MAX_BOUNCES;
+use crate::utils::{pcg_host, pcgf_host};
+use crate::voxel::TRANSMITTANCE_CUTOFF;
+
+fn mul(a: Vec3<f32>, b: Vec3<f32>) -> Vec3<f32> {
+    Vec3::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+fn add(a: Vec3<f32>, b: Vec3<f32>) -> Vec3<f32> {
+    Vec3::new(a.x + b.x, a.y + b.y, a.z + b.z)
+}
+fn dot(a: Vec2<f32>, b: Vec2<f32>) -> f32 {
+    a.x * b.x + a.y * b.y
+}
+fn length(a: Vec2<f32>) -> f32 {
+    dot(a, a).sqrt()
+}
+
+impl Fluence {
+    pub fn over(self, far: Fluence) -> Fluence {
+        Fluence {
+            radiance: add(mul(self.transmittance, far.radiance), self.radiance),
+            transmittance: mul(self.transmittance, far.transmittance),
+        }
+    }
+}
+impl Color {
+    pub fn to_fluence(self, segment_length: f32) -> Fluence {
+        let transmittance = self.opacity.map(|x| (-x * segment_length).exp());
+        Fluence {
+            radiance: mul(self.emission, transmittance.map(|t| 1.0 - t)),
+            transmittance,
+        }
+    }
+}
+
+pub fn aabb_intersect(
+    start: Vec2<f32>,
+    inv_dir: Vec2<f32>,
+    aabb_min: Vec2<f32>,
+    aabb_max: Vec2<f32>,
+) -> Vec2<f32> {
+    let t0 = Vec2::new(
+        (aabb_min.x - start.x) * inv_dir.x,
+        (aabb_min.y - start.y) * inv_dir.y,
+    );
+    let t1 = Vec2::new(
+        (aabb_max.x - start.x) * inv_dir.x,
+        (aabb_max.y - start.y) * inv_dir.y,
+    );
+    let tmin = t0.x.min(t1.x).max(t0.y.min(t1.y));
+    let tmax = t0.x.max(t1.x).min(t0.y.max(t1.y));
+    Vec2::new(tmin, tmax)
+}
+
+/// Cell-by-cell DDA through a `size.x * size.y` grid stored row-major,
+/// matching the clipping, cutoff and opaque diffuse cells of [`VoxelTracer::trace`].
+pub fn trace_voxel(
+    size: Vec2<u32>,
+    cells: &[Material],
+    start: Vec2<f32>,
+    ray_dir: Vec2<f32>,
+    ray_interval: Vec2<f32>,
+) -> Fluence {
+    let inv_dir = Vec2::new(
+        (ray_dir.x + f32::EPSILON).recip(),
+        (ray_dir.y + f32::EPSILON).recip(),
+    );
+    let interval = aabb_intersect(
+        start,
+        inv_dir,
+        Vec2::splat(0.1),
+        Vec2::new(size.x as f32 - 0.1, size.y as f32 - 0.1),
+    );
+    let start_t = interval.x.max(ray_interval.x);
+    let ray_start = Vec2::new(start.x + start_t * ray_dir.x, start.y + start_t * ray_dir.y);
+    let end_t = interval.y.min(ray_interval.y) - start_t;
+
+    let mut fluence = Fluence::empty();
+    if end_t <= 0.01 {
+        return fluence;
+    }
+
+    let mut pos = [ray_start.x.floor() as i32, ray_start.y.floor() as i32];
+    let ray_start = [ray_start.x, ray_start.y];
+    let ray_dir = [ray_dir.x, ray_dir.y];
+    let delta_dist = [inv_dir.x.abs(), inv_dir.y.abs()];
+    let mut side_dist = [0.0; 2];
+    for i in 0..2 {
+        let sign = ray_dir[i].signum();
+        side_dist[i] = (sign * (pos[i] as f32 - ray_start[i]) + sign * 0.5 + 0.5) * delta_dist[i];
+    }
+
+    let mut last_t = 0.0;
+    let mut first = true;
+    loop {
+        let next_t = side_dist[0].min(side_dist[1]);
+        let material = cells[(pos[0] as u32 + pos[1] as u32 * size.x) as usize];
+        if !first && material.is_diffuse() {
+            fluence.transmittance = Vec3::splat(0.0);
+            break;
+        }
+        first = false;
+        fluence = fluence.over(material.color().to_fluence(next_t.min(end_t) - last_t));
+        last_t = next_t;
+
+        if fluence.transmittance.x < TRANSMITTANCE_CUTOFF
+            && fluence.transmittance.y < TRANSMITTANCE_CUTOFF
+            && fluence.transmittance.z < TRANSMITTANCE_CUTOFF
+        {
+            fluence.transmittance = Vec3::splat(0.0);
+            break;
+        }
+        if next_t >= end_t {
+            break;
+        }
+
+        let axis = if side_dist[0] <= side_dist[1] { 0 } else { 1 };
+        side_dist[axis] += delta_dist[axis];
+        pos[axis] += ray_dir[axis].signum() as i32;
+        if pos[axis] < 0 || pos[axis] as u32 >= [size.x, size.y][axis] {
+            break;
+        }
+    }
+    fluence
+}
+
+/// Host version of [`crate::utils::diffuse_direction`].
+pub fn diffuse_direction(normal: Vec2<f32>, rand: f32) -> Vec2<f32> {
+    let sin = rand * 2.0 - 1.0;
+    let tangent = Vec2::new(normal.y, -normal.x);
+    tangent * sin + normal * (1.0 - sin * sin).sqrt()
+}
+
+/// Host version of `analytic::sample_slope`.
+pub fn sample_slope(alpha: f32, rand: f32) -> f32 {
+    let x = rand * 2.0 - 1.0;
+    alpha * x / (1.0 - x * x).max(1e-6).sqrt()
+}
+
+/// Host version of `analytic::fresnel`.
+pub fn fresnel(cos_i: f32, eta: f32) -> f32 {
+    let sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t2).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (rs * rs + rp * rp) * 0.5
+}
+
+/// Host version of [`AnalyticTracer::trace`].
+pub fn trace_analytic(
+    objects: &[Object],
+    pos: Vec2<f32>,
+    dir: Vec2<f32>,
+    len: f32,
+    seed: u32,
+) -> TracedRay {
+    let mut pos = pos;
+    let mut dir = dir;
+    let mut len = len;
+    let mut material = Material::empty();
+    let mut fluence = Fluence::empty();
+    let mut bounces = 0;
+    let mut events = 0;
+    let mut travelled = 0.0;
+    let mut steps = 0;
+    let mut refractions = 0;
+    for object in objects {
+        if length(pos - object.center) < object.radius {
+            material = object.material;
+        }
+    }
+    loop {
+        // Closest hit, as in `AnalyticTracer::trace_once`.
+        let mut distance = f32::INFINITY;
+        let mut normal = Vec2::splat(0.0);
+        let mut hit_object = 0;
+        let mut leaving = false;
+        for (i, object) in objects.iter().enumerate() {
+            let ray_start = pos - object.center;
+            let dist_to_parallel = -dot(ray_start, dir);
+            let min_point = ray_start + dir * dist_to_parallel;
+            let dist_to_center = length(min_point);
+            if object.radius - dist_to_center < 0.0 {
+                continue;
+            }
+            let dist_to_intersection = (object.radius.powi(2) - dist_to_center.powi(2)).sqrt();
+            let min_t = dist_to_parallel - dist_to_intersection;
+            let max_t = dist_to_parallel + dist_to_intersection;
+            if 0.001 < min_t && min_t < distance {
+                distance = min_t;
+                normal = ray_start + dir * min_t;
+                hit_object = i;
+                leaving = false;
+            } else if 0.001 < max_t && max_t < distance {
+                distance = max_t;
+                normal = ray_start + dir * max_t;
+                hit_object = i;
+                leaving = true;
+            }
+        }
+        let normal = normal * length(normal).recip();
+        steps += 1;
+
+        if distance > len {
+            pos = pos + dir * len;
+            travelled += len;
+            fluence = fluence.over(material.color().to_fluence(len));
+            break;
+        }
+        pos = pos + dir * distance;
+        len -= distance;
+        travelled += distance;
+        fluence = fluence.over(material.color().to_fluence(distance));
+        let normal = if leaving { normal } else { normal * -1.0 };
+        let obj = objects[hit_object];
+        let rand = [
+            pcgf_host(seed ^ pcg_host(events * 2)),
+            pcgf_host(seed ^ pcg_host(events * 2 + 1)),
+        ];
+        events += 1;
+        if !leaving && obj.material.is_diffuse() {
+            fluence = fluence.over(Fluence {
+                radiance: Vec3::splat(0.0),
+                transmittance: obj.material.albedo,
+            });
+            bounces += 1;
+            if bounces > MAX_BOUNCES
+                || (fluence.transmittance.x < TRANSMITTANCE_CUTOFF
+                    && fluence.transmittance.y < TRANSMITTANCE_CUTOFF
+                    && fluence.transmittance.z < TRANSMITTANCE_CUTOFF)
+            {
+                fluence.transmittance = Vec3::splat(0.0);
+                break;
+            }
+            dir = diffuse_direction(normal * -1.0, rand[0]);
+            continue;
+        }
+        let next_material = if leaving {
+            Material::empty()
+        } else {
+            obj.material
+        };
+        let eta = material.ior / next_material.ior;
+        let tangent = Vec2::new(normal.y, -normal.x);
+        let slope = sample_slope(obj.material.roughness.powi(2), rand[0]);
+        let micro = normal + tangent * slope;
+        let micro = micro * length(micro).recip();
+        let micro = if dot(micro, dir) > 0.0 { micro } else { normal };
+        let cos_i = dot(micro, dir);
+        let reflected = dir - micro * (2.0 * cos_i);
+        let smooth_reflected = dir - normal * (2.0 * dot(normal, dir));
+        if rand[1] < fresnel(cos_i, eta) {
+            dir = if dot(reflected, normal) < 0.0 {
+                reflected
+            } else {
+                smooth_reflected
+            };
+        } else {
+            let angle = (1.0 - cos_i.powi(2)).sqrt() * eta;
+            let micro_tangent = Vec2::new(micro.y, -micro.x);
+            let sign = dot(dir, micro_tangent).signum();
+            let refracted = micro_tangent * (angle * sign) + micro * (1.0 - angle.powi(2)).sqrt();
+            if dot(refracted, normal) > 0.0 {
+                dir = refracted;
+                material = next_material;
+                refractions += 1;
+            } else {
+                dir = smooth_reflected;
+            }
+        }
+    }
+    TracedRay {
+        fluence,
+        final_pos: pos,
+        final_dir: dir,
+        length: travelled,
+        steps,
+        refractions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::{Block, BlockSize, DEFAULT_LEVELS, with_block};
+
+    const RAYS: u32 = 4096;
+
+    fn random_rays(size: f32) -> Vec<(Vec2<f32>, Vec2<f32>)> {
+        (0..RAYS)
+            .map(|i| {
+                let pos = Vec2::new(pcgf_host(3 * i) * size, pcgf_host(3 * i + 1) * size);
+                let dir = (pcgf_host(3 * i + 2) * TAU).direction();
+                (pos, dir)
+            })
+            .collect()
+    }
+
+    fn close(a: Vec3<f32>, b: Vec3<f32>, tolerance: f32) -> bool {
+        [(a.x, b.x), (a.y, b.y), (a.z, b.z)]
+            .iter()
+            .all(|(a, b)| (a - b).abs() <= tolerance * (1.0 + a.abs().max(b.abs())))
+    }
+
+    /// Checks that every ray not in `skipped` agrees, with a tolerance growing with the cells the
+    /// ray crosses, as each adds a rounding error; lists every ray that doesn't.
+    fn check(
+        rays: &[(Vec2<f32>, Vec2<f32>)],
+        length: f32,
+        skipped: &[bool],
+        host: &[Fluence],
+        device: &[Fluence],
+    ) {
+        let mismatches = (0..rays.len())
+            .filter(|&i| {
+                let (_, dir) = rays[i];
+                let cells = (dir.x.abs() + dir.y.abs()) * length;
+                let tolerance = 1e-5 * (1.0 + cells);
+                let (h, d) = (host[i], device[i]);
+                !skipped[i]
+                    && !(close(h.radiance, d.radiance, tolerance)
+                        && close(h.transmittance, d.transmittance, tolerance))
+            })
+            .map(|i| {
+                format!(
+                    "ray {i} from {:?} along {:?}: host {:?}, device {:?}",
+                    rays[i].0, rays[i].1, host[i], device[i]
+                )
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            mismatches.is_empty(),
+            "{} of {} rays differ between host and device:\n{}",
+            mismatches.len(),
+            rays.len(),
+            mismatches.join("\n")
+        );
+    }
+
+    /// How far the ray travels inside the world before the voxel tracer clips it.
+    fn traced_length(size: Vec2<u32>, start: Vec2<f32>, dir: Vec2<f32>) -> f32 {
+        let inv_dir = Vec2::new(
+            (dir.x + f32::EPSILON).recip(),
+            (dir.y + f32::EPSILON).recip(),
+        );
+        let interval = aabb_intersect(
+            start,
+            inv_dir,
+            Vec2::splat(0.1),
+            Vec2::new(size.x as f32 - 0.1, size.y as f32 - 0.1),
+        );
+        interval.y - interval.x.max(0.0)
+    }
+
+    /// Whether the ray passes within `epsilon` of a cell corner inside `size` before it has
+    /// travelled `length`, where rounding decides which of the cells around it the DDA steps
+    /// through.
+    fn grazes_corner(
+        size: Vec2<u32>,
+        start: Vec2<f32>,
+        dir: Vec2<f32>,
+        length: f32,
+        epsilon: f32,
+    ) -> bool {
+        let grazes =
+            |start: f32, dir: f32, size: u32, other_start: f32, other_dir: f32, other_size: u32| {
+                (0..=size).any(|line| {
+                    let t = (line as f32 - start) / dir;
+                    let other = other_start + t * other_dir;
+                    t > 0.0
+                        && t <= length
+                        && (0.0..=other_size as f32).contains(&other)
+                        && (other - other.round()).abs() < epsilon
+                })
+            };
+        grazes(start.x, dir.x, size.x, start.y, dir.y, size.y)
+            || grazes(start.y, dir.y, size.y, start.x, dir.x, size.x)
+    }
+
+    fn check_voxel<B: Block>(levels: u32) {
+        let size = Vec2::splat(256);
+        let world = VoxelTracer::<B>::with_levels(size, levels);
+        Scene::sunflower4().scaled(0.25).draw(&world);
+        let cells = world.download();
+
+        let rays = random_rays(256.0);
+        let starts = DEVICE.create_buffer_from_fn(rays.len(), |i| rays[i].0);
+        let dirs = DEVICE.create_buffer_from_fn(rays.len(), |i| rays[i].1);
+        let out = DEVICE.create_buffer::<Fluence>(rays.len());
+        DEVICE
+            .create_kernel::<fn()>(&track!(|| {
+                let i = dispatch_id().x;
+                let fluence =
+                    world.trace(starts.read(i), dirs.read(i), Vec2::new(0.0, 9999.0).expr());
+                out.write(i, fluence);
+            }))
+            .dispatch([RAYS, 1, 1]);
+
+        let host = rays
+            .iter()
+            .map(|&(pos, dir)| trace_voxel(size, &cells, pos, dir, Vec2::new(0.0, 9999.0)))
+            .collect::<Vec<_>>();
+        let skipped = rays
+            .iter()
+            .map(|&(pos, dir)| grazes_corner(size, pos, dir, traced_length(size, pos, dir), 1e-3))
+            .collect::<Vec<_>>();
+        // About a fifth of these rays pass near a corner; the rest must still be checked.
+        let checked = skipped.iter().filter(|&&skip| !skip).count();
+        assert!(checked > rays.len() * 3 / 4, "only {checked} rays checked");
+        // No ray is longer than the diagonal of the world.
+        check(&rays, 2.0 * 256.0, &skipped, &host, &out.copy_to_vec());
+    }
+
+    #[test]
+    fn voxel_matches_host() {
+        for block_size in BlockSize::ALL {
+            with_block!(block_size, B => {
+                check_voxel::<B>(1);
+                check_voxel::<B>(DEFAULT_LEVELS);
+            });
+        }
+    }
+
+    fn check_analytic(objects: &[Object]) {
+        let world = AnalyticTracer::new(objects);
+
+        let rays = random_rays(256.0);
+        let starts = DEVICE.create_buffer_from_fn(rays.len(), |i| rays[i].0);
+        let dirs = DEVICE.create_buffer_from_fn(rays.len(), |i| rays[i].1);
+        let out = DEVICE.create_buffer::<Fluence>(rays.len());
+        DEVICE
+            .create_kernel::<fn()>(&track!(|| {
+                let i = dispatch_id().x;
+                let traced = world.trace(starts.read(i), dirs.read(i), 200.0.expr(), i);
+                out.write(i, traced.fluence);
+            }))
+            .dispatch([RAYS, 1, 1]);
+
+        let host = rays
+            .iter()
+            .enumerate()
+            .map(|(i, &(pos, dir))| trace_analytic(objects, pos, dir, 200.0, i as u32).fluence)
+            .collect::<Vec<_>>();
+        let skipped = vec![false; rays.len()];
+        check(&rays, 200.0, &skipped, &host, &out.copy_to_vec());
+    }
+
+    #[test]
+    fn procedural_matches_host() {
+        let scene = Scene::circle_field(64, 2.0, 32.0, 1).scaled(0.25);
+        check_analytic(&scene.objects());
+    }
+
+    #[test]
+    fn analytic_matches_host() {
+        let objects = [
+            Object {
+                center: Vec2::new(192.0, 128.0),
+                radius: 5.0,
+                material: Material::new(Vec3::splat(20.0), Vec3::splat(2.0)),
+            },
+            Object {
+                center: Vec2::new(128.0, 128.0),
+                radius: 40.0,
+                material: Material::new(Vec3::splat(0.0), Vec3::splat(0.1))
+                    .with_ior(1.5)
+                    .with_roughness(0.3),
+            },
+            Object {
+                center: Vec2::new(64.0, 200.0),
+                radius: 20.0,
+                material: Material::diffuse(Vec3::new(0.8, 0.5, 0.2)),
+            },
+        ];
+        check_analytic(&objects);
+    }
+}
@@ -0,0 +1,625 @@
+use super::*;
+use crate::scene::Draw;
+use crate::voxel::DEFAULT_LEVELS;
+
+/// Probes closer than this many window pixels aren't drawn, as they would cover the picture.
+const MIN_PROBE_SPACING: f32 = 4.0;
+
+/// Everything sized by the render resolution: the worlds, the guide, the estimates and the
+/// kernels using them. Changing the resolution builds a new one.
+pub struct Renderer {
+    pub size: u32,
+    pub world: AnalyticTracer,
+    voxels: Option<VoxelTracer>,
+    /// The draws rasterized into `voxels`, part of what the guide was learned for.
+    voxel_draws: Option<Vec<Draw>>,
+    pub storage: CascadeStorage,
+    pub next_storage: CascadeStorage,
+    display: Tex2d<Vec3<f32>>,
+    pub convergence: Convergence,
+    pub sampler: AdaptiveSampler,
+    pub aovs: Aovs,
+    pub denoiser: Denoiser,
+    pub histogram: Histogram,
+    pub emitters: Emitters,
+    pub light: LightTracer,
+    pub photons: PhotonMap,
+    radiance: Buffer<Radiance>,
+    copy_storage: Kernel<fn(f32, f32, f32, bool)>,
+    clear_display: Kernel<fn()>,
+    clear_sampler: Kernel<fn()>,
+    error_kernel: Kernel<fn()>,
+    allocate_kernel: Kernel<fn(f32, f32, u32)>,
+    histogram_kernel: Kernel<fn()>,
+    light_kernel: Kernel<fn(u32)>,
+    emit_kernel: Kernel<fn(u32)>,
+    gather_kernel: Kernel<fn()>,
+    trace_kernel: Kernel<fn(u32, f32, bool, u32, bool)>,
+    radiance_kernel: Kernel<fn(u32, bool)>,
+    features_kernel: Kernel<fn()>,
+    denoise_input_kernel: Kernel<fn()>,
+    denoise_kernels: Vec<Kernel<fn()>>,
+}
+
+impl Renderer {
+    /// Builds the renderer for `scene` and its analytic `objects`, both already at `size`.
+    pub fn new(config: &Config, scene: &Scene, objects: &[Object], size: u32) -> Self {
+        let world = AnalyticTracer::new(objects);
+        let voxels = (config.tracer == TracerKind::Voxel).then(|| {
+            // Sized for the scene, which may have more materials than the default capacity.
+            let voxels =
+                VoxelTracer::with_materials(Vec2::splat(size), DEFAULT_LEVELS, &scene.materials());
+            scene.draw(&voxels);
+            voxels
+        });
+        let tracer: &dyn PathTracer = match &voxels {
+            Some(voxels) => voxels,
+            None => &world,
+        };
+
+        let [storage, next_storage] = [(); 2]
+            .map(|()| CascadeStorage::new(Vec2::splat(size), config.base_angles, config.cascades));
+
+        let display = DEVICE.create_tex2d::<Vec3<f32>>(PixelStorage::Float4, size, size, 1);
+
+        // let compute_diff = DEVICE.create_kernel::<fn()>(&track!(|| {
+        //     world.compute_diff();
+        // }));
+        let copy_storage = DEVICE.create_kernel::<fn(f32, f32, f32, bool)>(&track!(
+            |keep, scale, prior, clear| {
+                let index = dispatch_id().x;
+                storage.data.write(
+                    index,
+                    storage.data.read(index) * keep + next_storage.data.read(index) * scale + prior,
+                );
+                if clear {
+                    next_storage.data.write(index, 0.0);
+                }
+            }
+        ));
+        let convergence = Convergence::new(Vec2::splat(size));
+        let sampler = AdaptiveSampler::new(Vec2::splat(size));
+        let aovs = Aovs::new(Vec2::splat(size));
+
+        let clear_display = DEVICE.create_kernel::<fn()>(&track!(|| {
+            display.write(dispatch_id().xy(), Vec3::splat_expr(0.0_f32));
+            convergence.clear(dispatch_id().xy());
+            sampler.clear(dispatch_id().xy());
+            aovs.clear(dispatch_id().xy());
+        }));
+        let clear_sampler = DEVICE.create_kernel::<fn()>(&track!(|| {
+            sampler.clear(dispatch_id().xy());
+        }));
+        let error_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            convergence.accumulate_error(pixel, display.read(pixel));
+        }));
+        let allocate_kernel =
+            DEVICE.create_kernel::<fn(f32, f32, u32)>(&track!(|mean_error, budget, t| {
+                let pixel = dispatch_id().xy();
+                sampler.allocate(
+                    &convergence,
+                    pixel,
+                    display.read(pixel),
+                    mean_error,
+                    budget,
+                    t,
+                );
+            }));
+        let histogram = Histogram::new();
+        let histogram_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            histogram.add(convergence.mean(pixel, display.read(pixel)));
+        }));
+        let emitters = Emitters::new(world.capacity());
+        emitters.update(objects);
+        let light = LightTracer::new(Vec2::splat(size), size * size / 4);
+        let light_kernel = DEVICE.create_kernel::<fn(u32)>(&track!(|seed| {
+            light.trace(&world, &emitters, seed);
+        }));
+        let photons = PhotonMap::new(Vec2::splat(size), size * size / 16, 1 << 22, 4.0);
+        let emit_kernel = DEVICE.create_kernel::<fn(u32)>(&track!(|seed| {
+            photons.emit(&world, &emitters, seed);
+        }));
+        let gather_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            photons.gather(dispatch_id().xy());
+        }));
+
+        // Directions are drawn from a mixture of the guide and uniform sampling, with
+        // `uniform_fraction` the probability of the latter. Weighting by the mixture pdf bounds the
+        // sample weight by `1 / uniform_fraction`, so poorly learned regions of the guide can't
+        // produce fireflies. `index_offset` keeps training iterations, which aren't recorded in
+        // `convergence`, from reusing the same samples.
+        let trace_kernel = DEVICE.create_kernel::<fn(u32, f32, bool, u32, bool)>(&track!(
+            |sequence, uniform_fraction, accumulate, index_offset, record_aovs| {
+                let pixel = dispatch_id().xy();
+                let samples = 1 + sampler.extra(pixel);
+                let estimate = Radiance::splat(0.0).var();
+                for sample in 0_u32.expr()..samples {
+                    let sample_index = convergence.samples.read(pixel) + sample + index_offset;
+                    let stream = SampleStream::new(sequence, pixel, sample_index);
+                    // Bounce directions aren't guided, so they come from hashing instead of the
+                    // stream.
+                    let path_seed = pcg3d(pixel.extend(sample_index)).x;
+                    let pos = pixel.cast_f32() + 0.5;
+                    let (index, bias, dir) =
+                        storage.sample_direction(&stream, pos, uniform_fraction);
+                    let segments = storage.trace_segments(tracer, pos, dir, path_seed);
+
+                    let mut radiance = Radiance::splat(0.0).expr();
+
+                    for i in (0..storage.num_cascades).rev() {
+                        radiance = segments[i as usize].fluence.over_radiance(radiance);
+                        let index =
+                            index >> (storage.angular_scale * (storage.num_cascades - 1 - i));
+                        next_storage.add_bilinear(
+                            i.expr(),
+                            pos,
+                            index,
+                            luma(radiance) / bias / samples.cast_f32(),
+                        );
+                    }
+
+                    *estimate += radiance / bias / samples.cast_f32();
+                    if accumulate && record_aovs {
+                        let path = segments
+                            .iter()
+                            .fold(Fluence::empty().expr(), |path, segment| {
+                                path.over(segment.fluence)
+                            });
+                        let length = segments
+                            .iter()
+                            .fold(0.0_f32.expr(), |length, segment| length + segment.length);
+                        let steps = segments
+                            .iter()
+                            .fold(0_u32.expr(), |steps, segment| steps + segment.steps);
+                        let refractions = segments
+                            .iter()
+                            .fold(0_u32.expr(), |count, segment| count + segment.refractions);
+                        aovs.record(
+                            pixel,
+                            [
+                                path.transmittance,
+                                Vec3::splat_expr(length),
+                                Vec3::splat_expr(steps.cast_f32()),
+                                Vec3::splat_expr(refractions.cast_f32()),
+                                Vec3::splat_expr(index.cast_f32()),
+                                Vec3::splat_expr(bias),
+                            ],
+                        );
+                    }
+                }
+                // Each iteration adds the mean of its samples, so the display stays unbiased
+                // however many samples the adaptive sampler gave the pixel.
+                if accumulate {
+                    display.write(pixel, display.read(pixel) + **estimate);
+                    convergence.record(pixel, **estimate, samples);
+                }
+            }
+        ));
+        let radiance = DEVICE.create_buffer::<Radiance>((size * size) as usize);
+        let denoiser = Denoiser::new(Vec2::splat(size));
+        let features_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            denoiser.write_features(pixel, tracer.material_at(pixel.cast_f32() + 0.5));
+        }));
+        let denoise_input_kernel = DEVICE.create_kernel::<fn()>(&track!(|| {
+            let pixel = dispatch_id().xy();
+            let sum = display.read(pixel);
+            let mean = convergence.mean(pixel, sum);
+            // Undoes the epsilon of `relative_error`, giving the standard error itself.
+            let deviation = convergence.relative_error(pixel, sum) * (luma(mean) + 0.001);
+            denoiser.write_input(pixel, mean, deviation);
+        }));
+        let denoise_kernels = (0..PASSES)
+            .map(|pass| {
+                DEVICE.create_kernel::<fn()>(&track!(|| {
+                    denoiser.filter(dispatch_id().xy(), pass);
+                }))
+            })
+            .collect::<Vec<_>>();
+        features_kernel.dispatch([size, size, 1]);
+
+        let radiance_kernel = DEVICE.create_kernel::<fn(u32, bool)>(&track!(|aov, denoised| {
+            let pixel = dispatch_id().xy();
+            let value = if aov == Aov::Radiance as u32 {
+                if denoised {
+                    denoiser.output().read(pixel)
+                } else {
+                    convergence.mean(pixel, display.read(pixel))
+                }
+            } else {
+                aovs.mean(pixel, aov)
+            };
+            radiance.write(pixel.x + pixel.y * size, value);
+        }));
+
+        Self {
+            size,
+            world,
+            voxel_draws: voxels.is_some().then(|| scene.draws.clone()),
+            voxels,
+            storage,
+            next_storage,
+            display,
+            convergence,
+            sampler,
+            aovs,
+            denoiser,
+            histogram,
+            emitters,
+            light,
+            photons,
+            radiance,
+            copy_storage,
+            clear_display,
+            clear_sampler,
+            error_kernel,
+            allocate_kernel,
+            histogram_kernel,
+            light_kernel,
+            emit_kernel,
+            gather_kernel,
+            trace_kernel,
+            radiance_kernel,
+            features_kernel,
+            denoise_input_kernel,
+            denoise_kernels,
+        }
+    }
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.size, self.size, 1]
+    }
+    pub fn track_memory(&self, stats: &mut Stats) {
+        stats.track_memory("storage", self.storage.memory_bytes());
+        stats.track_memory("next_storage", self.next_storage.memory_bytes());
+        stats.track_memory(
+            "display",
+            (self.size * self.size) as usize * std::mem::size_of::<Vec4<f32>>(),
+        );
+        stats.track_memory("aovs", self.aovs.memory_bytes());
+        stats.track_memory("denoiser", self.denoiser.memory_bytes());
+        stats.track_memory("world", self.world.memory_bytes());
+        if let Some(voxels) = &self.voxels {
+            stats.track_memory("voxels", voxels.memory_bytes());
+        }
+    }
+    /// Uploads the edited `objects` and discards all estimates and the guide's pending samples.
+    pub fn reset(&mut self, objects: &[Object], learner: &Learner) {
+        self.world.upload(objects);
+        self.features_kernel.dispatch(self.dispatch_size());
+        self.clear_display.dispatch(self.dispatch_size());
+        self.emitters.update(objects);
+        self.light.clear();
+        self.photons.clear();
+        self.next_storage
+            .data
+            .copy_from(&vec![learner.initial_next(); self.next_storage.data.len()]);
+    }
+    pub fn toggle_adaptive_sampling(&mut self) {
+        self.sampler.enabled ^= true;
+        println!("Adaptive sampling: {}", self.sampler.enabled);
+        if !self.sampler.enabled {
+            self.clear_sampler.dispatch(self.dispatch_size());
+            self.sampler.extra_samples = 0;
+        }
+    }
+    /// One iteration of guided tracing and guide learning.
+    pub fn render_iteration(
+        &mut self,
+        learner: &mut Learner,
+        stats: &mut Stats,
+        iterations: &mut u32,
+        error: &mut Option<f32>,
+        sequence: Sequence,
+        uniform_fraction: f32,
+    ) {
+        let training = learner.training();
+        stats.time("trace_kernel", || {
+            self.trace_kernel.dispatch(
+                self.dispatch_size(),
+                &(sequence as u32),
+                &uniform_fraction,
+                &!training,
+                &if training {
+                    TRAINING_INDEX_OFFSET + learner.iteration()
+                } else {
+                    0
+                },
+                &self.aovs.enabled,
+            )
+        });
+        if !training {
+            *iterations += 1;
+        }
+        let pixels = self.size as u64 * self.size as u64;
+        stats.sample(pixels + self.sampler.extra_samples, !training);
+        if let Some(update) = learner.step() {
+            stats.time("copy_storage", || {
+                self.copy_storage.dispatch(
+                    [self.storage.data.len() as u32, 1, 1],
+                    &update.keep,
+                    &update.scale,
+                    &update.prior,
+                    &update.clear,
+                )
+            });
+        }
+        if !training && *iterations % ERROR_INTERVAL == 0 {
+            stats.time("error_kernel", || {
+                self.error_kernel.dispatch(self.dispatch_size())
+            });
+            let mean_error = self.convergence.take_mean_error();
+            *error = Some(mean_error);
+            if self.sampler.enabled {
+                self.allocate_kernel.dispatch(
+                    self.dispatch_size(),
+                    &mean_error,
+                    &self.sampler.budget,
+                    &*iterations,
+                );
+                self.sampler.extra_samples = self.sampler.take_total();
+            }
+        }
+    }
+    pub fn trace_light(&mut self, stats: &mut Stats) {
+        stats.time("light_kernel", || {
+            self.light_kernel
+                .dispatch([self.light.paths, 1, 1], &self.light.passes)
+        });
+        self.light.passes += 1;
+    }
+    pub fn map_photons(&mut self, stats: &mut Stats) {
+        stats.time("photon_kernels", || {
+            self.photons.begin_pass();
+            self.emit_kernel
+                .dispatch([self.photons.paths, 1, 1], &self.photons.passes);
+            self.gather_kernel.dispatch(self.dispatch_size());
+        });
+        self.photons.passes += 1;
+    }
+    /// Denoises the current estimate into [`Denoiser::output`].
+    pub fn denoise(&self, stats: &mut Stats) {
+        stats.time("denoise_kernels", || {
+            self.denoise_input_kernel.dispatch(self.dispatch_size());
+            for kernel in &self.denoise_kernels {
+                kernel.dispatch(self.dispatch_size());
+            }
+        });
+    }
+    pub fn update_histogram(&self) {
+        self.histogram.clear();
+        self.histogram_kernel.dispatch(self.dispatch_size());
+    }
+    /// Replaces the guide with the one saved at `path`, if it was learned for `objects` and the
+    /// same tracer and world, with the same layout.
+    pub fn load_guide(&self, path: &Path, objects: &[Object]) -> Result<(), String> {
+        guide::load(
+            path,
+            &self.storage,
+            &self.next_storage,
+            self.guide_checksum(objects),
+        )
+    }
+    pub fn save_guide(&self, path: &Path, objects: &[Object]) {
+        let checksum = self.guide_checksum(objects);
+        match guide::save(path, &self.storage, &self.next_storage, checksum) {
+            Ok(()) => println!("Saved guide to {}", path.display()),
+            Err(err) => eprintln!("Failed to save guide to {}: {err}", path.display()),
+        }
+    }
+    fn guide_checksum(&self, objects: &[Object]) -> u64 {
+        guide::checksum(objects, self.voxel_draws.as_deref(), &self.storage)
+    }
+    /// Writes the mean of `aov` to `path` as a PFM image; [`Aov::Radiance`] is the guided
+    /// estimate, denoised if the denoiser is enabled.
+    pub fn save(&self, path: &Path, aov: Aov, stats: &mut Stats) -> std::io::Result<()> {
+        let denoised = self.denoiser.enabled && aov == Aov::Radiance;
+        if denoised {
+            self.denoise(stats);
+        }
+        self.radiance_kernel
+            .dispatch(self.dispatch_size(), &(aov as u32), &denoised);
+        write_pfm(path, Vec2::splat(self.size), &self.radiance.copy_to_vec())
+    }
+}
+
+/// Exposure, tonemapper, false color, light tracing passes and weight, photon mapping passes and
+/// weight, AOV and denoising.
+type DrawFn = fn(f32, u32, bool, u32, f32, u32, f32, u32, bool);
+
+/// The kernels drawing a [`Renderer`] to a window, scaling from the render resolution to the
+/// window's.
+pub struct Presenter {
+    window_size: u32,
+    draw_kernel: Kernel<DrawFn>,
+    plot: Buffer<f32>,
+    draw_inspector: Kernel<fn(Vec2<f32>, f32, u32)>,
+    draw_probe_overlay: Kernel<fn(u32)>,
+}
+impl Presenter {
+    pub fn new(renderer: &Renderer, target: &Tex2d<Vec3<f32>>) -> Self {
+        let window_size = target.width();
+        let Renderer {
+            storage,
+            display,
+            convergence,
+            aovs,
+            denoiser,
+            light,
+            photons,
+            ..
+        } = renderer;
+        // Render pixel shown at a window pixel.
+        let scale = renderer.size as f32 / window_size as f32;
+        let source = move |pixel: Expr<Vec2<u32>>| (pixel.cast_f32() * scale).cast_u32();
+
+        let draw_kernel = DEVICE.create_kernel::<DrawFn>(&track!(
+            |exposure,
+             tonemapper,
+             show_false_color,
+             light_passes,
+             light_weight,
+             photon_passes,
+             photon_weight,
+             aov,
+             denoised| {
+                let pixel = dispatch_id().xy();
+                let src = source(pixel);
+                let color = if aov == Aov::Radiance as u32 {
+                    let mean = if denoised {
+                        denoiser.output().read(src)
+                    } else {
+                        convergence.mean(src, display.read(src))
+                    };
+                    mean * (1.0 - light_weight - photon_weight)
+                        + light.mean(src, light_passes) * light_weight
+                        + photons.mean(src, photon_passes) * photon_weight
+                } else {
+                    aovs.mean(src, aov)
+                };
+                let color = color * exposure;
+                let color = if show_false_color {
+                    false_color(color)
+                } else {
+                    tonemap(color, tonemapper)
+                };
+                target.write(pixel, color);
+            }
+        ));
+        let angles = (0..storage.num_cascades)
+            .map(|i| storage.base_angles << (storage.angular_scale * i))
+            .collect::<Vec<_>>();
+        // Offset of each cascade in `plot`.
+        let offsets = angles
+            .iter()
+            .scan(0, |offset, &angles| {
+                *offset += angles;
+                Some(*offset - angles)
+            })
+            .collect::<Vec<_>>();
+        // Each cascade's plotted pdf, one value per direction.
+        let plot = DEVICE.create_buffer::<f32>(angles.iter().sum::<u32>() as usize);
+        let draw_inspector =
+            DEVICE.create_kernel::<fn(Vec2<f32>, f32, u32)>(&track!(|center, radius, cascades| {
+                let pixel = dispatch_id().xy();
+                let delta = pixel.cast_f32() + 0.5 - center;
+                let dist = delta.length();
+                if dist > radius + 1.0 {
+                    return;
+                }
+                let angle = (delta.angle() / TAU).rem_euclid(1.0);
+                let color = target.read(pixel).var();
+                for i in (0..angles.len()) {
+                    if ((cascades >> i as u32) & 1) == 1 {
+                        let index =
+                            keter::min((angle * angles[i] as f32).cast_u32(), angles[i] - 1);
+                        let r = plot.read(index + offsets[i]) * radius;
+                        let cascade_color = cascade_colors()[i].expr();
+                        // Outline the curve and tint the area within it.
+                        if (dist - r).abs() < 1.0 {
+                            *color = cascade_color;
+                        } else if dist < r {
+                            *color = **color * 0.7 + cascade_color * 0.3;
+                        }
+                    }
+                }
+                if dist < 2.0 {
+                    *color = Vec3::splat(1.0);
+                }
+                target.write(pixel, **color);
+            }));
+        let draw_probe_overlay = DEVICE.create_kernel::<fn(u32)>(&track!(|cascade| {
+            let pos = dispatch_id().xy().cast_f32() + 0.5;
+            let pos = pos * storage.base_spacing * (1 << cascade).cast_f32() / scale;
+            let pixel = pos.floor().cast_u32();
+            if (pixel < window_size).all() {
+                target.write(pixel, cascade_colors().expr().read(cascade));
+            }
+        }));
+        Self {
+            window_size,
+            draw_kernel,
+            plot,
+            draw_inspector,
+            draw_probe_overlay,
+        }
+    }
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.window_size, self.window_size, 1]
+    }
+    /// Whether this draws to a display of the size of `target`.
+    pub fn fits(&self, target: &Tex2d<Vec3<f32>>) -> bool {
+        target.width() == self.window_size
+    }
+    /// Converts a window position to render coordinates.
+    pub fn to_render(&self, renderer: &Renderer, pos: Vec2<f32>) -> Vec2<f32> {
+        pos * (renderer.size as f32 / self.window_size as f32)
+    }
+    pub fn draw(
+        &self,
+        renderer: &Renderer,
+        stats: &mut Stats,
+        settings: &DisplaySettings,
+        light_tracing: LightTracing,
+        show_photons: bool,
+        aov: Aov,
+    ) {
+        let exposure = settings.exposure(&renderer.histogram);
+        let denoised = renderer.denoiser.enabled && aov == Aov::Radiance;
+        if denoised {
+            renderer.denoise(stats);
+        }
+        let light = &renderer.light;
+        let photons = &renderer.photons;
+        stats.time("draw_kernel", || {
+            self.draw_kernel.dispatch(
+                self.dispatch_size(),
+                &exposure,
+                &(settings.tonemapper as u32),
+                &settings.false_color,
+                &light.passes,
+                // The photon map replaces the other estimates rather than averaging with them.
+                &if light.passes > 0 && !show_photons {
+                    light_tracing.weight()
+                } else {
+                    0.0
+                },
+                &photons.passes,
+                &if show_photons && photons.passes > 0 {
+                    1.0
+                } else {
+                    0.0
+                },
+                &(aov as u32),
+                &denoised,
+            )
+        });
+    }
+    /// Draws the distribution of directions at the inspected position as a polar plot around
+    /// it, with one curve per shown cascade.
+    pub fn draw_inspector(&self, renderer: &Renderer, inspector: &Inspector, probe: &ProbeWeights) {
+        let Some(pos) = inspector.pos else {
+            return;
+        };
+        self.plot.copy_from(&probe.plot());
+        let center = pos * (self.window_size as f32 / renderer.size as f32);
+        self.draw_inspector.dispatch(
+            self.dispatch_size(),
+            &center,
+            &(self.window_size as f32 / 6.0),
+            &inspector.cascades,
+        );
+    }
+    /// Draws the probes of the cascades shown by `inspector` that are at least
+    /// [`MIN_PROBE_SPACING`] apart.
+    pub fn draw_probes(&self, renderer: &Renderer, inspector: &Inspector) {
+        let storage = &renderer.storage;
+        let scale = self.window_size as f32 / renderer.size as f32;
+        let sparse = |i: u32| storage.base_spacing * (1 << i) as f32 * scale >= MIN_PROBE_SPACING;
+        for i in (0..storage.num_cascades).filter(|&i| inspector.shows(i) && sparse(i)) {
+            self.draw_probe_overlay
+                .dispatch([storage.base_size.x >> i, storage.base_size.y >> i, 1], &i);
+        }
+    }
+}
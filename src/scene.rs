@@ -1,6 +1,8 @@
 use palette::{FromColor, LinSrgb, Oklch};
 
 use super::*;
+use crate::material::library;
+use crate::voxel::Block;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Brush {
@@ -12,9 +14,10 @@ pub enum Brush {
 pub struct Draw {
     pub brush: Brush,
     pub center: Vec2<f32>,
-    pub color: Color,
+    pub material: Material,
 }
 
+#[derive(Clone)]
 pub struct Scene {
     pub draws: Vec<Draw>,
 }
@@ -25,17 +28,65 @@ impl Scene {
             draws: draws.to_vec(),
         }
     }
+    /// Scales all positions and brush sizes by `factor`, e.g. to fit a smaller world.
+    pub fn scaled(mut self, factor: f32) -> Self {
+        for draw in &mut self.draws {
+            draw.center = draw.center * factor;
+            draw.brush = match draw.brush {
+                Brush::Rect(width, height) => Brush::Rect(width * factor, height * factor),
+                Brush::Circle(radius) => Brush::Circle(radius * factor),
+            };
+        }
+        self
+    }
     pub fn simple() -> Self {
         Self::new([
             Draw {
                 brush: Brush::Circle(1.0),
                 center: Vec2::new(256.0, 256.0),
-                color: Color::new(Vec3::splat(5.0), Vec3::splat(100.0)),
+                material: Material::new(Vec3::splat(5.0), Vec3::splat(100.0)),
             },
             Draw {
                 brush: Brush::Rect(20.0, 5.0),
                 center: Vec2::new(256.0, 384.0),
-                color: Color::new(Vec3::splat(0.0), Vec3::splat(100.0)),
+                material: Material::new(Vec3::splat(0.0), Vec3::splat(100.0)),
+            },
+        ])
+    }
+    /// A closed room with white walls, lit by a small light behind a partition,
+    /// so most of it is only lit indirectly.
+    pub fn room() -> Self {
+        let wall = Material::diffuse(Vec3::splat(0.8));
+        Self::new([
+            Draw {
+                brush: Brush::Rect(512.0, 16.0),
+                center: Vec2::new(512.0, 16.0),
+                material: wall,
+            },
+            Draw {
+                brush: Brush::Rect(512.0, 16.0),
+                center: Vec2::new(512.0, 1008.0),
+                material: wall,
+            },
+            Draw {
+                brush: Brush::Rect(16.0, 512.0),
+                center: Vec2::new(16.0, 512.0),
+                material: wall,
+            },
+            Draw {
+                brush: Brush::Rect(16.0, 512.0),
+                center: Vec2::new(1008.0, 512.0),
+                material: wall,
+            },
+            Draw {
+                brush: Brush::Rect(8.0, 320.0),
+                center: Vec2::new(768.0, 352.0),
+                material: Material::diffuse(Vec3::new(0.8, 0.3, 0.2)),
+            },
+            Draw {
+                brush: Brush::Circle(8.0),
+                center: Vec2::new(896.0, 160.0),
+                material: Material::new(Vec3::splat(50.0), Vec3::splat(100.0)),
             },
         ])
     }
@@ -45,12 +96,12 @@ impl Scene {
             Draw {
                 brush: Brush::Rect(1.0, 512.0 + l),
                 center: Vec2::new(512.0, -5.0),
-                color: Color::solid(Vec3::splat(0.0)),
+                material: Material::solid(Vec3::splat(0.0)),
             },
             Draw {
                 brush: Brush::Rect(1.0, 512.0 - l),
                 center: Vec2::new(512.0, 1024.0 + 5.0),
-                color: Color::solid(Vec3::splat(0.0)),
+                material: Material::solid(Vec3::splat(0.0)),
             },
         ];
         for i in -3..=3 {
@@ -59,7 +110,7 @@ impl Scene {
             draws.push(Draw {
                 brush: Brush::Rect(5.0, 20.0),
                 center: Vec2::new(1024.0 - 1000.0, -i as f32 * 40.0 + 512.0),
-                color: Color::solid(Vec3::new(
+                material: Material::solid(Vec3::new(
                     50.0 * color.red.max(0.0) as f32,
                     50.0 * color.green.max(0.0) as f32,
                     50.0 * color.blue.max(0.0) as f32,
@@ -84,7 +135,7 @@ impl Scene {
             draws.push(Draw {
                 brush: Brush::Circle(10.0),
                 center: pos,
-                color: Color::new(
+                material: Material::new(
                     Vec3::new(
                         5.0 * color.red.max(0.0),
                         5.0 * color.green.max(0.0),
@@ -97,3 +148,151 @@ impl Scene {
         Self { draws }
     }
 }
+
+impl Scene {
+    /// One circle per object.
+    pub fn from_objects(objects: &[Object]) -> Self {
+        Self {
+            draws: objects
+                .iter()
+                .map(|object| Draw {
+                    brush: Brush::Circle(object.radius),
+                    center: object.center,
+                    material: object.material,
+                })
+                .collect(),
+        }
+    }
+    /// The built-in scenes selectable by name, in a world of width 1024;
+    /// `seed` is used by the procedural ones.
+    pub fn named(name: &str, seed: u32) -> Option<Self> {
+        Some(match name {
+            "lenses" => Scene::from_objects(&lenses(1024.0)),
+            "simple" => Scene::simple(),
+            "room" => Scene::room(),
+            "pinhole" => Scene::pinhole(0),
+            "sunflower4" => Scene::sunflower4(),
+            _ => {
+                Scene::stress_tests(seed)
+                    .into_iter()
+                    .find(|(stress_name, _)| *stress_name == name)?
+                    .1
+            }
+        })
+    }
+    /// Parses a scene file: one draw per line, either `circle <x> <y> <radius> <material>` or
+    /// `rect <x> <y> <half width> <half height> <material>`, with the material one of the
+    /// [`library`](crate::material::library) names. Blank lines and lines starting with `#` are
+    /// ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let materials = library();
+        let mut draws = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {message}", i + 1);
+            let mut words = line.split_whitespace();
+            let kind = words.next().unwrap();
+            let arity = match kind {
+                "circle" => 3,
+                "rect" => 4,
+                _ => {
+                    return Err(error(format!(
+                        "unknown shape `{kind}`, expected `circle` or `rect`"
+                    )));
+                }
+            };
+            let numbers = words
+                .by_ref()
+                .take(arity)
+                .map(|word| {
+                    word.parse::<f32>()
+                        .map_err(|_| error(format!("`{word}` is not a number")))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if numbers.len() < arity {
+                return Err(error(format!(
+                    "`{kind}` needs {arity} numbers and a material"
+                )));
+            }
+            let name = words.collect::<Vec<_>>().join(" ");
+            let material = materials
+                .iter()
+                .find(|(material_name, _)| *material_name == name)
+                .ok_or_else(|| {
+                    let names = materials.map(|(name, _)| name).join(", ");
+                    error(format!(
+                        "unknown material `{name}`, expected one of: {names}"
+                    ))
+                })?
+                .1;
+            draws.push(Draw {
+                brush: if kind == "circle" {
+                    Brush::Circle(numbers[2])
+                } else {
+                    Brush::Rect(numbers[2], numbers[3])
+                },
+                center: Vec2::new(numbers[0], numbers[1]),
+                material,
+            });
+        }
+        Ok(Self { draws })
+    }
+    /// The distinct materials of the scene, with the empty material first as the background.
+    pub fn materials(&self) -> Vec<Material> {
+        let mut materials = vec![Material::empty()];
+        for draw in &self.draws {
+            if !materials.contains(&draw.material) {
+                materials.push(draw.material);
+            }
+        }
+        materials
+    }
+    /// Rasterizes the scene into `world`, replacing its material table, and rebuilds its
+    /// occupancy levels.
+    pub fn draw<B: Block>(&self, world: &VoxelTracer<B>) {
+        let materials = self.materials();
+        world.materials.upload(&materials);
+        let id = |material: &Material| materials.iter().position(|m| m == material).unwrap() as u32;
+
+        let rect_brush = DEVICE.create_kernel::<fn(Vec2<f32>, Vec2<f32>, MaterialId)>(&track!(
+            |center, size, material| {
+                let pos = dispatch_id().xy();
+                if ((pos.cast_f32() + 0.5 - center).abs() < size).all() {
+                    world.write(pos, material);
+                }
+            }
+        ));
+        let circle_brush = DEVICE.create_kernel::<fn(Vec2<f32>, f32, MaterialId)>(&track!(
+            |center, radius, material| {
+                let pos = dispatch_id().xy();
+                if (pos.cast_f32() + 0.5 - center).length() < radius {
+                    world.write(pos, material);
+                }
+            }
+        ));
+        for draw in &self.draws {
+            match draw.brush {
+                Brush::Rect(width, height) => {
+                    rect_brush.dispatch(
+                        [world.size.x, world.size.y, 1],
+                        &draw.center,
+                        &Vec2::new(width, height),
+                        &id(&draw.material),
+                    );
+                }
+                Brush::Circle(radius) => {
+                    circle_brush.dispatch(
+                        [world.size.x, world.size.y, 1],
+                        &draw.center,
+                        &radius,
+                        &id(&draw.material),
+                    );
+                }
+            }
+        }
+        world.update();
+    }
+}
@@ -0,0 +1,226 @@
+use super::*;
+use crate::utils::pcg;
+
+/// Sample sequences selectable at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Sequence {
+    /// Independent hashed random numbers.
+    Random,
+    /// The R_d sequence, randomly rotated per pixel.
+    Rd,
+    /// Owen-scrambled Sobol, padded to higher dimensions with shuffled 4D sets.
+    Sobol,
+    /// The R_d sequence, with blue noise per-pixel rotations.
+    BlueNoise,
+}
+impl Sequence {
+    pub const ALL: [Sequence; 4] = [
+        Sequence::Random,
+        Sequence::Rd,
+        Sequence::Sobol,
+        Sequence::BlueNoise,
+    ];
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Dimensions of the R_d sequence: the cascade choices, the jitter and the choice between guided
+/// and uniform sampling of [`CascadeStorage::sample_direction`] for up to six cascades.
+pub const RD_DIMENSIONS: u32 = 8;
+
+/// The steps of each dimension of the R_d sequence in 0.32 fixed point; in `f32`,
+/// `index * alpha` loses its fractional part once indices reach 2^24, as training indices do.
+// https://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+fn rd_alphas() -> [u32; RD_DIMENSIONS as usize] {
+    // The unique positive root of x^(d + 1) = x + 1.
+    let mut phi = 2.0_f64;
+    for _ in 0..64 {
+        phi = (1.0 + phi).powf(1.0 / (RD_DIMENSIONS + 1) as f64);
+    }
+    std::array::from_fn(|i| (phi.powi(-(i as i32 + 1)).fract() * 4_294_967_296.0) as u32)
+}
+
+/// Sobol direction numbers for the first four dimensions (Joe and Kuo).
+fn sobol_directions() -> [[u32; 32]; 4] {
+    let mut directions = [[0; 32]; 4];
+    for (i, v) in directions[0].iter_mut().enumerate() {
+        *v = 1 << (31 - i);
+    }
+    let polynomials: [(usize, u32, &[u32]); 3] =
+        [(1, 0, &[1]), (2, 1, &[1, 3]), (3, 1, &[1, 3, 1])];
+    for (dim, (s, a, m)) in polynomials.into_iter().enumerate() {
+        let v = &mut directions[dim + 1];
+        for i in 0..32 {
+            v[i] = if i < s {
+                m[i] << (31 - i)
+            } else {
+                let mut x = v[i - s] ^ (v[i - s] >> s);
+                for k in 1..s {
+                    if (a >> (s - 1 - k)) & 1 != 0 {
+                        x ^= v[i - k];
+                    }
+                }
+                x
+            };
+        }
+    }
+    directions
+}
+
+#[tracked]
+fn reverse_bits(x: Expr<u32>) -> Expr<u32> {
+    let x = ((x >> 1) & 0x55555555) | ((x & 0x55555555) << 1);
+    let x = ((x >> 2) & 0x33333333) | ((x & 0x33333333) << 2);
+    let x = ((x >> 4) & 0x0F0F0F0F) | ((x & 0x0F0F0F0F) << 4);
+    let x = ((x >> 8) & 0x00FF00FF) | ((x & 0x00FF00FF) << 8);
+    (x >> 16) | (x << 16)
+}
+
+// Burley 2020, "Practical Hash-based Owen Scrambling"
+#[tracked]
+fn nested_uniform_scramble(x: Expr<u32>, seed: Expr<u32>) -> Expr<u32> {
+    let x = reverse_bits(x) + seed;
+    let x = x ^ (x * 0x6c50b47c);
+    let x = x ^ (x * 0xb82f1e52);
+    let x = x ^ (x * 0xc7afe638);
+    let x = x ^ (x * 0x8d22f6e6);
+    reverse_bits(x)
+}
+
+#[tracked]
+fn sobol(index: Expr<u32>, directions: [u32; 32]) -> Expr<u32> {
+    let directions = directions.expr();
+    let index = index.var();
+    let result = 0_u32.var();
+    for bit in 0_u32..32_u32 {
+        if index == 0 {
+            break;
+        }
+        if index & 1 != 0 {
+            *result ^= directions[bit];
+        }
+        *index >>= 1;
+    }
+    **result
+}
+
+/// Maps 0.32 fixed point to `[0, 1)`, keeping the 24 bits an `f32` can hold.
+#[tracked]
+fn to_unit(x: Expr<u32>) -> Expr<f32> {
+    (x >> 8).cast_f32() / (1 << 24) as f32
+}
+
+// Jimenez 2014, "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+#[tracked]
+fn interleaved_gradient_noise(pixel: Expr<Vec2<f32>>) -> Expr<f32> {
+    (52.982_918 * (pixel.dot(Vec2::expr(0.067_110_56, 0.005_837_15))).fract()).fract()
+}
+
+/// The samples for one pixel and sample index; each dimension should be used for one decision,
+/// consistently across samples.
+pub struct SampleStream {
+    sequence: Expr<u32>,
+    pixel: Expr<Vec2<u32>>,
+    index: Expr<u32>,
+}
+impl SampleStream {
+    pub fn new(sequence: Expr<u32>, pixel: Expr<Vec2<u32>>, index: Expr<u32>) -> Self {
+        Self {
+            sequence,
+            pixel,
+            index,
+        }
+    }
+    #[tracked]
+    fn hash(&self, dimension: u32) -> Expr<u32> {
+        pcg(pcg(self.pixel.x ^ pcg(self.pixel.y)) ^ dimension)
+    }
+    /// Dimension `dimension` of the R_d sequence offset by `offset`, in fixed point; wrapping
+    /// arithmetic takes the fractional part.
+    #[tracked]
+    fn rd(&self, dimension: u32, offset: Expr<u32>) -> Expr<f32> {
+        assert!(
+            dimension < RD_DIMENSIONS,
+            "the R_d sequence has {RD_DIMENSIONS} dimensions, got dimension {dimension}"
+        );
+        to_unit(offset + self.index * rd_alphas()[dimension as usize])
+    }
+    #[tracked]
+    pub fn get(&self, dimension: u32) -> Expr<f32> {
+        if self.sequence == Sequence::Random as u32 {
+            pcg3df(self.pixel.extend(self.index * 64 + dimension)).x
+        } else if self.sequence == Sequence::Rd as u32 {
+            self.rd(dimension, self.hash(dimension))
+        } else if self.sequence == Sequence::Sobol as u32 {
+            // Each group of four dimensions is a separately shuffled and scrambled 4D set.
+            let group_seed = self.hash(dimension / 4 + 0x1000);
+            let index = nested_uniform_scramble(self.index, group_seed);
+            let x = sobol(index, sobol_directions()[dimension as usize % 4]);
+            to_unit(nested_uniform_scramble(x, pcg(group_seed ^ dimension)))
+        } else {
+            let shift = Vec2::new(dimension as f32 * 5.588_238, dimension as f32 * 3.146_271);
+            let offset = interleaved_gradient_noise(self.pixel.cast_f32() + shift.expr());
+            self.rd(dimension, (offset * (1 << 24) as f32).cast_u32() << 8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::eval;
+
+    #[test]
+    fn training_indices_give_distinct_samples() {
+        const SAMPLES: u32 = 64;
+        for sequence in Sequence::ALL {
+            for dimension in [0, 1, 7] {
+                let mut samples = eval(SAMPLES, |i| {
+                    let pixel = Vec2::new(3, 5).expr();
+                    SampleStream::new((sequence as u32).expr(), pixel, i + TRAINING_INDEX_OFFSET)
+                        .get(dimension)
+                });
+                samples.sort_by(f32::total_cmp);
+                samples.dedup();
+                assert_eq!(
+                    samples.len(),
+                    SAMPLES as usize,
+                    "{sequence:?}, dimension {dimension}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rd_dimensions_are_uncorrelated() {
+        const SAMPLES: u32 = 1024;
+        let dimensions = (0..RD_DIMENSIONS)
+            .map(|dimension| {
+                eval(SAMPLES, |i| {
+                    let pixel = Vec2::new(3, 5).expr();
+                    SampleStream::new((Sequence::Rd as u32).expr(), pixel, i).get(dimension)
+                })
+            })
+            .collect::<Vec<_>>();
+        let correlation = |a: &[f32], b: &[f32]| {
+            let n = a.len() as f32;
+            let mean = |x: &[f32]| x.iter().sum::<f32>() / n;
+            let (ma, mb) = (mean(a), mean(b));
+            let cov = a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - ma) * (y - mb))
+                .sum::<f32>();
+            let var = |x: &[f32], m: f32| x.iter().map(|x| (x - m).powi(2)).sum::<f32>();
+            cov / (var(a, ma) * var(b, mb)).sqrt()
+        };
+        for i in 0..dimensions.len() {
+            for j in i + 1..dimensions.len() {
+                let r = correlation(&dimensions[i], &dimensions[j]);
+                assert!(r.abs() < 0.1, "dimensions {i} and {j}: {r}");
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Timing {
+    total: Duration,
+    count: u32,
+}
+
+/// Periodically logged render statistics.
+pub struct Stats {
+    /// Whether kernels are synchronized and timed individually; this slows rendering down.
+    pub profiling: bool,
+    pixels: u64,
+    memory: Vec<(&'static str, usize)>,
+    timings: Vec<(&'static str, Timing)>,
+    last_log: Instant,
+    samples_since_log: u64,
+    /// Samples added to the display since it was last cleared.
+    displayed_samples: u64,
+}
+impl Stats {
+    pub fn new(pixels: u64) -> Self {
+        Self {
+            profiling: false,
+            pixels,
+            memory: vec![],
+            timings: vec![],
+            last_log: Instant::now(),
+            samples_since_log: 0,
+            displayed_samples: 0,
+        }
+    }
+    /// Registers a named allocation to report.
+    pub fn track_memory(&mut self, name: &'static str, bytes: usize) {
+        self.memory.push((name, bytes));
+    }
+    pub fn time(&mut self, name: &'static str, f: impl FnOnce()) {
+        if !self.profiling {
+            f();
+            return;
+        }
+        let stream = DEVICE.default_stream();
+        stream.synchronize();
+        let start = Instant::now();
+        f();
+        stream.synchronize();
+        let elapsed = start.elapsed();
+        let timing = match self.timings.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, timing)) => timing,
+            None => {
+                self.timings.push((name, Timing::default()));
+                &mut self.timings.last_mut().unwrap().1
+            }
+        };
+        timing.total += elapsed;
+        timing.count += 1;
+    }
+    /// Mean duration of the calls timed as `name` since the last log, if any were while profiling.
+    pub fn mean_time(&self, name: &str) -> Option<Duration> {
+        self.timings
+            .iter()
+            .find(|(n, timing)| *n == name && timing.count > 0)
+            .map(|(_, timing)| timing.total / timing.count)
+    }
+    /// Records the `samples` traced by an iteration, which were added to the display if
+    /// `displayed`.
+    pub fn sample(&mut self, samples: u64, displayed: bool) {
+        self.samples_since_log += samples;
+        if displayed {
+            self.displayed_samples += samples;
+        }
+    }
+    /// Restarts the samples per pixel, for when the display is cleared.
+    pub fn clear_samples(&mut self) {
+        self.displayed_samples = 0;
+    }
+    /// Prints statistics if enough time has passed since the last log.
+    pub fn log(&mut self, iterations: u32, error: Option<f32>) {
+        let elapsed = self.last_log.elapsed();
+        if elapsed < LOG_INTERVAL {
+            return;
+        }
+        println!(
+            "iterations: {}, spp: {:.1}, samples/s: {:.3e}",
+            iterations,
+            self.displayed_samples as f64 / self.pixels as f64,
+            self.samples_since_log as f64 / elapsed.as_secs_f64()
+        );
+        if let Some(error) = error {
+            println!("  relative error: {:.4}", error);
+        }
+        for (name, timing) in &self.timings {
+            if timing.count > 0 {
+                println!(
+                    "  {}: {:.3} ms",
+                    name,
+                    timing.total.as_secs_f64() * 1000.0 / timing.count as f64
+                );
+            }
+        }
+        let total = self.memory.iter().map(|(_, bytes)| bytes).sum::<usize>();
+        println!("  memory: {:.1} MiB", total as f64 / (1 << 20) as f64);
+        for (name, bytes) in &self.memory {
+            println!("    {}: {:.1} MiB", name, *bytes as f64 / (1 << 20) as f64);
+        }
+        for (_, timing) in &mut self.timings {
+            *timing = Timing::default();
+        }
+        self.last_log = Instant::now();
+        self.samples_since_log = 0;
+    }
+}
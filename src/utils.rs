@@ -79,7 +79,66 @@ pub fn pcg3df(v: Expr<Vec3<u32>>) -> Expr<Vec3<f32>> {
     pcg3d(v).cast_f32() / u32::MAX as f32
 }
 
+/// Samples the 2D cosine-weighted distribution of directions around `normal`.
+/// The pdf cancels the cosine and the 1/2 of a 2D Lambertian BRDF, so a bounce just scales by the albedo.
+#[tracked]
+pub fn diffuse_direction(normal: Expr<Vec2<f32>>, rand: Expr<f32>) -> Expr<Vec2<f32>> {
+    let sin = rand * 2.0 - 1.0;
+    let tangent = Vec2::expr(normal.y, -normal.x);
+    sin * tangent + (1.0 - sin.sqr()).sqrt() * normal
+}
+
 #[tracked]
 pub fn gaussian(v: Expr<f32>) -> Expr<f32> {
     (-v * v).exp()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    use super::*;
+    use crate::tests::eval;
+
+    #[test]
+    fn aabb_intersect_hits_and_misses() {
+        let cases = [
+            // start, dir, expected [t_enter, t_exit]
+            (Vec2::new(-1.0, 0.5), Vec2::new(1.0, 0.0), Some([1.0, 2.0])),
+            (Vec2::new(0.5, 0.5), Vec2::new(0.0, -1.0), Some([-0.5, 0.5])),
+            (
+                Vec2::new(-1.0, -1.0),
+                Vec2::splat(FRAC_1_SQRT_2),
+                Some([2.0_f32.sqrt(), 8.0_f32.sqrt()]),
+            ),
+            (Vec2::new(-1.0, 2.0), Vec2::new(1.0, 0.0), None),
+        ];
+        let device = eval(cases.len() as u32, |i| {
+            let starts = cases.map(|c| c.0).expr();
+            let dirs = cases.map(|c| c.1).expr();
+            aabb_intersect(
+                starts.read(i),
+                (dirs.read(i) + f32::EPSILON).recip(),
+                Vec2::splat(0.0).expr(),
+                Vec2::splat(1.0).expr(),
+            )
+        });
+        for (interval, (_, _, expected)) in device.into_iter().zip(cases) {
+            match expected {
+                Some([enter, exit]) => {
+                    assert!((interval.x - enter).abs() < 1e-4, "{interval:?}");
+                    assert!((interval.y - exit).abs() < 1e-4, "{interval:?}");
+                }
+                None => assert!(interval.x > interval.y, "{interval:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn pcg_host_matches_device() {
+        let device = eval(1 << 16, |i| pcg(i * 7919));
+        for (i, x) in device.into_iter().enumerate() {
+            assert_eq!(x, pcg_host(i as u32 * 7919));
+        }
+    }
+}
@@ -1,11 +1,15 @@
-use crate::utils::aabb_intersect;
+use crate::analytic::{MAX_BOUNCES, TracedRayComps};
+use crate::utils::{aabb_intersect, diffuse_direction, pcg, pcgf};
 
 use super::*;
 
 pub trait Block: Value {
     type Storage: IoTexel;
     const STORAGE_FORMAT: PixelStorage;
+    /// Number of storage texels per block along x.
+    const STORAGE_WIDTH: u32 = 1;
     const SIZE: u32;
+    const SHIFT: u32 = Self::SIZE.trailing_zeros();
 
     fn read(storage: &Tex2dView<Self::Storage>, offset: Expr<Vec2<u32>>) -> Expr<Self>;
     fn write(storage: &Tex2dView<Self::Storage>, offset: Expr<Vec2<u32>>, value: Expr<Self>);
@@ -101,79 +105,236 @@ impl Block for u64 {
     }
 }
 
-type BlockType = u64;
+#[derive(Clone, Copy, Debug, PartialEq, Value)]
+#[repr(C)]
+pub struct Block16 {
+    lo: Vec4<u32>,
+    hi: Vec4<u32>,
+}
+impl Block16 {
+    #[tracked]
+    fn word_mask(offset: Expr<Vec2<u32>>) -> (Expr<bool>, Expr<Vec4<u32>>) {
+        let index = offset.x + offset.y * 16;
+        let bit = 1_u32 << (index % 32);
+        let word = Vec4::splat_expr(index / 32 % 4);
+        let mask = (Vec4::expr(0_u32, 1, 2, 3) == word)
+            .select(Vec4::splat_expr(bit), Vec4::splat_expr(0_u32));
+        (index >= 128, mask)
+    }
+}
+impl Block for Block16 {
+    type Storage = Vec4<u32>;
+    const STORAGE_FORMAT: PixelStorage = PixelStorage::Int4;
+    const STORAGE_WIDTH: u32 = 2;
+    const SIZE: u32 = 16;
 
-pub struct VoxelTracer {
-    emission: Tex2d<Emission>,
-    opacity: Tex2d<Opacity>,
-    pub diff: Tex2d<<BlockType as Block>::Storage>,
-    pub diff_blocks: Tex2d<bool>,
+    #[tracked]
+    fn read(storage: &Tex2dView<Self::Storage>, offset: Expr<Vec2<u32>>) -> Expr<Self> {
+        let offset = offset * Vec2::expr(2, 1);
+        Block16::from_comps_expr(Block16Comps {
+            lo: storage.read(offset),
+            hi: storage.read(offset + Vec2::x()),
+        })
+    }
+    #[tracked]
+    fn write(storage: &Tex2dView<Self::Storage>, offset: Expr<Vec2<u32>>, value: Expr<Self>) {
+        let offset = offset * Vec2::expr(2, 1);
+        storage.write(offset, value.lo);
+        storage.write(offset + Vec2::x(), value.hi);
+    }
+    #[tracked]
+    fn get(this: Expr<Self>, offset: Expr<Vec2<u32>>) -> Expr<bool> {
+        let (high, mask) = Block16::word_mask(offset);
+        let words = if high { this.hi } else { this.lo };
+        (words & mask != 0).any()
+    }
+    #[tracked]
+    fn set(this: Var<Self>, offset: Expr<Vec2<u32>>) {
+        let (high, mask) = Block16::word_mask(offset);
+        if high {
+            *this.hi |= mask;
+        } else {
+            *this.lo |= mask;
+        }
+    }
+    #[tracked]
+    fn is_empty(this: Expr<Self>) -> Expr<bool> {
+        (this.lo == 0).all() && (this.hi == 0).all()
+    }
+    fn empty() -> Self {
+        Block16 {
+            lo: Vec4::splat(0),
+            hi: Vec4::splat(0),
+        }
+    }
+}
+
+/// Runtime choice of [`Block`] type, for use with [`with_block!`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockSize {
+    B1,
+    B4,
+    B8,
+    B16,
+}
+impl BlockSize {
+    pub const ALL: [BlockSize; 4] = [BlockSize::B1, BlockSize::B4, BlockSize::B8, BlockSize::B16];
+    pub fn size(self) -> u32 {
+        match self {
+            BlockSize::B1 => bool::SIZE,
+            BlockSize::B4 => u16::SIZE,
+            BlockSize::B8 => u64::SIZE,
+            BlockSize::B16 => Block16::SIZE,
+        }
+    }
+}
+
+/// Evaluates `$body` with `$B` bound to the [`Block`] type selected by `$size`.
+macro_rules! with_block {
+    ($size:expr, $B:ident => $body:expr) => {
+        match $size {
+            $crate::voxel::BlockSize::B1 => {
+                type $B = bool;
+                $body
+            }
+            $crate::voxel::BlockSize::B4 => {
+                type $B = u16;
+                $body
+            }
+            $crate::voxel::BlockSize::B8 => {
+                type $B = u64;
+                $body
+            }
+            $crate::voxel::BlockSize::B16 => {
+                type $B = $crate::voxel::Block16;
+                $body
+            }
+        }
+    };
+}
+pub(crate) use with_block;
+
+#[derive(Clone, Copy, Debug, PartialEq, Value)]
+#[repr(C)]
+struct SurfaceHit {
+    // Distance along the ray; infinite if no surface was hit.
+    t: f32,
+    normal: Vec2<f32>,
+    albedo: Albedo,
+}
+impl SurfaceHit {
+    fn none() -> Self {
+        SurfaceHit {
+            t: f32::INFINITY,
+            normal: Vec2::splat(0.0),
+            albedo: Vec3::splat(0.0),
+        }
+    }
+}
+
+/// Number of occupancy levels used by [`VoxelTracer::new`].
+pub const DEFAULT_LEVELS: u32 = 4;
+
+pub struct VoxelTracer<B: Block = u64> {
+    material: Tex2d<MaterialId>,
+    pub materials: MaterialTable,
+    pub diff: Tex2d<B::Storage>,
+    // `occupancy[0]` is set for blocks with a nonempty `diff`,
+    // and `occupancy[i]` is the or of the 2x2 cells of `occupancy[i - 1]` below it.
+    pub occupancy: Vec<Tex2d<bool>>,
     pub size: Vec2<u32>,
 }
-impl VoxelTracer {
+impl<B: Block> VoxelTracer<B> {
     pub fn new(size: Vec2<u32>) -> Self {
-        Self::with_storage(
-            size,
-            Emission::natural_storage(),
-            Opacity::natural_storage(),
-        )
+        Self::with_levels(size, DEFAULT_LEVELS)
+    }
+    pub fn with_levels(size: Vec2<u32>, levels: u32) -> Self {
+        Self::with_materials(size, levels, &[Material::empty()])
     }
 }
-impl VoxelTracer {
-    pub fn with_storage(
-        size: Vec2<u32>,
-        emission_storage: PixelStorage,
-        opacity_storage: PixelStorage,
-    ) -> Self {
+impl<B: Block> VoxelTracer<B> {
+    /// Creates an empty world, with every cell set to material 0 of `materials`.
+    pub fn with_materials(size: Vec2<u32>, levels: u32, materials: &[Material]) -> Self {
+        assert!(
+            levels >= 1,
+            "VoxelTracer needs at least one occupancy level"
+        );
+        assert!(
+            size.x % B::SIZE == 0 && size.y % B::SIZE == 0,
+            "VoxelTracer size {size:?} isn't a multiple of its {} cell blocks",
+            B::SIZE
+        );
+        let block_size = size / B::SIZE;
         Self {
-            emission: DEVICE.create_tex2d(emission_storage, size.x, size.y, 1),
-            opacity: DEVICE.create_tex2d(opacity_storage, size.x, size.y, 1),
-            diff: DEVICE.create_tex2d::<<BlockType as Block>::Storage>(
-                BlockType::STORAGE_FORMAT,
-                size.x / BlockType::SIZE,
-                size.y / BlockType::SIZE,
-                1,
-            ),
-            diff_blocks: DEVICE.create_tex2d::<bool>(
-                PixelStorage::Byte1,
-                size.x / BlockType::SIZE,
-                size.y / BlockType::SIZE,
+            material: DEVICE.create_tex2d(PixelStorage::Int1, size.x, size.y, 1),
+            materials: MaterialTable::new(materials),
+            diff: DEVICE.create_tex2d::<B::Storage>(
+                B::STORAGE_FORMAT,
+                block_size.x * B::STORAGE_WIDTH,
+                block_size.y,
                 1,
             ),
+            occupancy: (0..levels)
+                .map(|level| {
+                    // Rounded up, so cells at the edge cover the blocks past the last whole one.
+                    let round_up = |n: u32| (n + (1 << level) - 1) >> level;
+                    DEVICE.create_tex2d::<bool>(
+                        PixelStorage::Byte1,
+                        round_up(block_size.x),
+                        round_up(block_size.y),
+                        1,
+                    )
+                })
+                .collect(),
             size,
         }
     }
-    pub fn read(&self, pos: Expr<Vec2<u32>>) -> Expr<Color> {
-        Color::expr(self.emission.read(pos), self.opacity.read(pos))
+    pub fn levels(&self) -> u32 {
+        self.occupancy.len() as u32
     }
-    pub fn read_emission(&self, pos: Expr<Vec2<u32>>) -> Expr<Emission> {
-        self.emission.read(pos)
+    /// Approximate device memory use, assuming natural texel sizes.
+    pub fn memory_bytes(&self) -> usize {
+        let texels = |width: u32, height: u32| (width * height) as usize;
+        texels(self.size.x, self.size.y) * std::mem::size_of::<MaterialId>()
+            + self.materials.memory_bytes()
+            + texels(self.diff.width(), self.diff.height()) * std::mem::size_of::<B::Storage>()
+            + self
+                .occupancy
+                .iter()
+                .map(|level| texels(level.width(), level.height()))
+                .sum::<usize>()
     }
-    pub fn read_opacity(&self, pos: Expr<Vec2<u32>>) -> Expr<Opacity> {
-        self.opacity.read(pos)
+    pub fn read(&self, pos: Expr<Vec2<u32>>) -> Expr<Material> {
+        self.materials.read(self.material.read(pos))
     }
-    pub fn write(&self, pos: Expr<Vec2<u32>>, color: Expr<Color>) {
-        self.emission.write(pos, color.emission);
-        self.opacity.write(pos, color.opacity);
+    pub fn read_id(&self, pos: Expr<Vec2<u32>>) -> Expr<MaterialId> {
+        self.material.read(pos)
     }
-    pub fn write_emission(&self, pos: Expr<Vec2<u32>>, emission: Expr<Emission>) {
-        self.emission.write(pos, emission);
+    pub fn write(&self, pos: Expr<Vec2<u32>>, material: Expr<MaterialId>) {
+        self.material.write(pos, material);
     }
-    pub fn write_opacity(&self, pos: Expr<Vec2<u32>>, opacity: Expr<Opacity>) {
-        self.opacity.write(pos, opacity);
+    /// Copies the world back to the host, row-major.
+    pub fn download(&self) -> Vec<Material> {
+        let materials = self.materials.download();
+        self.material
+            .view(0)
+            .copy_to_vec::<MaterialId>()
+            .into_iter()
+            .map(|id| materials[id as usize])
+            .collect()
     }
 }
-const TRANSMITTANCE_CUTOFF: f32 = 0.001;
+pub(crate) const TRANSMITTANCE_CUTOFF: f32 = 0.001;
 
-impl VoxelTracer {
+impl<B: Block> VoxelTracer<B> {
     #[tracked]
     pub fn compute_diff(&self) {
-        let block = BlockType::empty().var();
-        for dx in 0..BlockType::SIZE {
-            for dy in 0..BlockType::SIZE {
-                let pos = dispatch_id().xy() * BlockType::SIZE + Vec2::expr(dx, dy);
+        let block = B::empty().var();
+        for dx in 0..B::SIZE {
+            for dy in 0..B::SIZE {
+                let pos = dispatch_id().xy() * B::SIZE + Vec2::expr(dx, dy);
                 let diff = false.var();
-                let color = self.read(pos);
+                let material = self.read_id(pos);
                 for i in 0_u32..4_u32 {
                     let offset = [
                         Vec2::new(1, 0),
@@ -184,31 +345,87 @@ impl VoxelTracer {
                     .expr()[i];
                     let neighbor = pos.cast_i32() + offset;
                     if (neighbor >= 0).all() && (neighbor < self.size.expr().cast_i32()).all() {
-                        let n_color = self.read(neighbor.cast_u32());
-                        if (color.emission != n_color.emission).any()
-                            || (color.opacity != n_color.opacity).any()
-                        {
+                        if material != self.read_id(neighbor.cast_u32()) {
                             *diff = true;
                             break;
                         }
                     }
                 }
                 if diff {
-                    BlockType::set(block, Vec2::expr(dx, dy));
+                    B::set(block, Vec2::expr(dx, dy));
                 }
             }
         }
-        self.diff_blocks
-            .write(dispatch_id().xy(), !BlockType::is_empty(**block));
-        BlockType::write(&self.diff.view(0), dispatch_id().xy(), **block);
+        self.occupancy[0].write(dispatch_id().xy(), !B::is_empty(**block));
+        B::write(&self.diff.view(0), dispatch_id().xy(), **block);
     }
+    /// Builds `occupancy[level]` from `occupancy[level - 1]`.
+    /// Must be dispatched over the size of `occupancy[level]`, once per level after [`Self::compute_diff`].
     #[tracked]
+    pub fn compute_occupancy(&self, level: u32) {
+        let below = &self.occupancy[level as usize - 1];
+        let below_size = Vec2::new(below.width(), below.height()).expr();
+        let occupied = false.var();
+        for offset in ([
+            Vec2::new(0, 0),
+            Vec2::new(1, 0),
+            Vec2::new(0, 1),
+            Vec2::new(1, 1),
+        ]) {
+            let pos = dispatch_id().xy() * 2 + offset.expr();
+            if (pos < below_size).all() && below.read(pos) {
+                *occupied = true;
+            }
+        }
+        self.occupancy[level as usize].write(dispatch_id().xy(), **occupied);
+    }
+    /// Recomputes `diff` and every occupancy level; call after writing to the world.
+    pub fn update(&self) {
+        let compute_diff = DEVICE.create_kernel::<fn()>(&track!(|| {
+            self.compute_diff();
+        }));
+        compute_diff.dispatch([self.size.x / B::SIZE, self.size.y / B::SIZE, 1]);
+        for level in 1..self.levels() {
+            let compute_occupancy = DEVICE.create_kernel::<fn()>(&track!(|| {
+                self.compute_occupancy(level);
+            }));
+            let occupancy = &self.occupancy[level as usize];
+            compute_occupancy.dispatch([occupancy.width(), occupancy.height(), 1]);
+        }
+    }
+    /// Traces along the ray, treating cells with nonzero albedo as opaque.
     pub fn trace(
         &self,
         start: Expr<Vec2<f32>>,
         ray_dir: Expr<Vec2<f32>>,
         ray_interval: Expr<Vec2<f32>>,
     ) -> Expr<Fluence> {
+        self.trace_counted(start, ray_dir, ray_interval).0
+    }
+    /// Same as [`Self::trace`], but also returns the number of traversal steps taken.
+    #[tracked]
+    pub fn trace_counted(
+        &self,
+        start: Expr<Vec2<f32>>,
+        ray_dir: Expr<Vec2<f32>>,
+        ray_interval: Expr<Vec2<f32>>,
+    ) -> (Expr<Fluence>, Expr<u32>) {
+        let (fluence, steps, hit) = self.trace_to_surface(start, ray_dir, ray_interval);
+        let fluence = fluence.var();
+        if hit.t < f32::INFINITY {
+            *fluence.transmittance = Vec3::splat(0.0);
+        }
+        (**fluence, steps)
+    }
+    /// Traces until the first cell with nonzero albedo entered along the ray, if any,
+    /// returning the fluence up to it along with the number of traversal steps.
+    #[tracked]
+    fn trace_to_surface(
+        &self,
+        start: Expr<Vec2<f32>>,
+        ray_dir: Expr<Vec2<f32>>,
+        ray_interval: Expr<Vec2<f32>>,
+    ) -> (Expr<Fluence>, Expr<u32>, Expr<SurfaceHit>) {
         let inv_dir = (ray_dir + f32::EPSILON).recip();
 
         let interval = aabb_intersect(
@@ -220,13 +437,15 @@ impl VoxelTracer {
         let start_t = keter::max(interval.x, ray_interval.x);
         let ray_start = start + start_t * ray_dir;
         let end_t = keter::min(interval.y, ray_interval.y) - start_t;
-        if end_t <= 0.01 {
-            Fluence::empty().expr()
-        } else {
+
+        let fluence = Fluence::empty().var();
+        let steps = 0_u32.var();
+        let hit = SurfaceHit::none().var();
+
+        if end_t > 0.01 {
             let pos = ray_start.floor().cast_u32().var();
 
             let delta_dist = inv_dir.abs();
-            let block_delta_dist = delta_dist * BlockType::SIZE as f32;
 
             let ray_step = ray_dir.signum().cast_i32().cast_u32();
             let side_dist =
@@ -234,30 +453,40 @@ impl VoxelTracer {
                     * delta_dist;
             let side_dist = side_dist.var();
 
-            let block_offset = (ray_dir > 0.0).select(
-                Vec2::splat_expr(0_u32),
-                Vec2::splat_expr(BlockType::SIZE - 1),
-            );
-
             let last_t = 0.0_f32.var();
-            let fluence = Fluence::empty().var();
+            // Axes crossed to enter the current cell, and where; none for the starting cell.
+            let entry = Vec2::splat_expr(false).var();
+            let entry_t = 0.0_f32.var();
 
             let finished = false.var();
 
             loop {
                 loop {
+                    *steps += 1;
                     let next_t = side_dist.reduce_min();
 
-                    let block = BlockType::read(&self.diff.view(0), pos / BlockType::SIZE);
+                    let block = B::read(&self.diff.view(0), pos >> B::SHIFT);
 
-                    if BlockType::is_empty(block) {
+                    if B::is_empty(block) {
                         break;
                     }
 
-                    if BlockType::get(block, pos % BlockType::SIZE) || next_t >= end_t {
+                    if B::get(block, pos % B::SIZE) || next_t >= end_t {
+                        let material = self.read(**pos);
+                        if entry.any() && material.is_diffuse() {
+                            *hit = SurfaceHit::from_comps_expr(SurfaceHitComps {
+                                t: start_t + entry_t,
+                                normal: entry
+                                    .select(-ray_dir.signum(), Vec2::splat_expr(0.0))
+                                    .normalize(),
+                                albedo: material.albedo,
+                            });
+                            *finished = true;
+                            break;
+                        }
+
                         let segment_size = keter::min(next_t, end_t) - last_t;
-                        let color = self.read(**pos);
-                        *fluence = fluence.over(color.to_fluence(segment_size));
+                        *fluence = fluence.over(material.color().to_fluence(segment_size));
 
                         *last_t = next_t;
 
@@ -277,57 +506,58 @@ impl VoxelTracer {
 
                     *side_dist += mask.select(delta_dist, Vec2::splat_expr(0.0));
                     *pos += mask.select(ray_step, Vec2::splat_expr(0));
+                    *entry = mask;
+                    *entry_t = next_t;
                 }
 
                 if finished {
                     break;
                 }
 
-                let block_pos = (pos / BlockType::SIZE).var();
-                let block_side_dist = (ray_dir.signum()
-                    * (block_pos.cast_f32() - ray_start / BlockType::SIZE as f32)
-                    + ray_dir.signum() * 0.5
-                    + 0.5)
-                    * block_delta_dist;
-                let block_side_dist = block_side_dist.var();
+                // Skip over empty space, using the coarsest empty occupancy cell containing `pos`.
+                loop {
+                    *steps += 1;
 
-                let next_t = block_side_dist.reduce_min().var();
+                    let level = 0_u32.var();
+                    for (i, occupancy) in (self.occupancy.iter().enumerate().skip(1)) {
+                        let i = i as u32;
+                        if level == i - 1 && !occupancy.read(pos >> (B::SHIFT + i)) {
+                            *level = i;
+                        }
+                    }
+                    let shift = B::SHIFT + level;
+                    let cell_min = (pos >> shift) << shift;
+                    let cell_max = cell_min + (1_u32 << shift);
+
+                    let exit = (ray_dir > 0.0).select(cell_max, cell_min).cast_f32();
+                    let exit_t = (exit - ray_start) * inv_dir;
+                    let next_t = exit_t.reduce_min();
 
-                loop {
                     if next_t >= end_t {
                         let segment_size = end_t - last_t;
-                        let color = self.read(**pos);
-                        *fluence = fluence.over(color.to_fluence(segment_size));
+                        let material = self.read(**pos);
+                        *fluence = fluence.over(material.color().to_fluence(segment_size));
 
                         *finished = true;
                         break;
                     }
 
-                    let mask = block_side_dist <= block_side_dist.yx();
-
-                    *block_side_dist += mask.select(block_delta_dist, Vec2::splat_expr(0.0));
-                    *block_pos += mask.select(ray_step, Vec2::splat_expr(0));
-
-                    let last_t = **next_t;
-                    *next_t = block_side_dist.reduce_min();
-
-                    if self.diff_blocks.read(block_pos) {
-                        *pos = mask.select(
-                            block_pos * BlockType::SIZE + block_offset,
-                            (last_t * ray_dir + ray_start).floor().cast_u32(),
-                        );
-                        // let a = (pos / B::SIZE == block_pos).all();
-                        // lc_assert!(a);
-                        // This bugfix is necessary due to floating point issues.
-                        if (pos / BlockType::SIZE != block_pos).any() {
-                            // *fluence = Fluence::black();
-                            *finished = true;
-                        }
+                    let mask = exit_t <= exit_t.yx();
+                    // Clamping the crossing to the cell face avoids landing in the wrong cell
+                    // due to floating point error.
+                    let crossing = (ray_start + next_t * ray_dir)
+                        .floor()
+                        .clamp(cell_min.cast_f32(), (cell_max - 1).cast_f32())
+                        .cast_u32();
+                    *pos = mask.select((ray_dir > 0.0).select(cell_max, cell_min - 1), crossing);
+                    *entry = mask;
+                    *entry_t = next_t;
+
+                    if self.occupancy[0].read(pos >> B::SHIFT) {
                         *side_dist = (ray_dir.signum() * (pos.cast_f32() - ray_start)
                             + ray_dir.signum() * 0.5
                             + 0.5)
                             * delta_dist;
-
                         break;
                     }
                 }
@@ -336,7 +566,61 @@ impl VoxelTracer {
                     break;
                 }
             }
-            **fluence
         }
+        (**fluence, **steps, **hit)
+    }
+}
+
+/// Bounces off cells with nonzero albedo as Lambertian surfaces, with `seed` choosing the bounce
+/// directions.
+impl<B: Block> PathTracer for VoxelTracer<B> {
+    #[tracked]
+    fn trace_path(
+        &self,
+        start: Expr<Vec2<f32>>,
+        ray_dir: Expr<Vec2<f32>>,
+        len: Expr<f32>,
+        seed: Expr<u32>,
+    ) -> Expr<TracedRay> {
+        let pos = start.var();
+        let dir = ray_dir.var();
+        let len = len.var();
+        let fluence = Fluence::empty().var();
+        let length = 0.0_f32.var();
+        let steps = 0_u32.var();
+        for bounce in 0_u32.expr()..(MAX_BOUNCES + 1).expr() {
+            let (segment, segment_steps, hit) =
+                self.trace_to_surface(**pos, **dir, Vec2::expr(0.0, **len));
+            *fluence = fluence.over(segment);
+            *steps += segment_steps;
+            if hit.t == f32::INFINITY {
+                *pos += len * dir;
+                *length += len;
+                break;
+            }
+            *fluence = fluence.over(Fluence::expr(Vec3::splat_expr(0.0), hit.albedo));
+            *length += hit.t;
+            if bounce == MAX_BOUNCES || (fluence.transmittance < TRANSMITTANCE_CUTOFF).all() {
+                *fluence.transmittance = Vec3::splat(0.0);
+                *pos += hit.t * dir;
+                break;
+            }
+            // Nudged off the face so the next segment starts outside the surface cell.
+            *pos += hit.t * dir + hit.normal * 0.01;
+            *len -= hit.t;
+            *dir = diffuse_direction(hit.normal, pcgf(seed ^ pcg(bounce + 1)));
+        }
+        TracedRay::from_comps_expr(TracedRayComps {
+            fluence: **fluence,
+            final_pos: **pos,
+            final_dir: **dir,
+            length: **length,
+            steps: **steps,
+            // Voxels only scatter diffusely.
+            refractions: 0_u32.expr(),
+        })
+    }
+    fn material_at(&self, pos: Expr<Vec2<f32>>) -> Expr<Material> {
+        self.read(pos.floor().cast_u32())
     }
 }